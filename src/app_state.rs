@@ -0,0 +1,114 @@
+use bevy::prelude::*;
+
+use crate::{game_tile::MapData, tile_world::TileAssets};
+
+/// Gameplay systems only run in `Playing`
+#[derive(States, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AppState {
+    #[default]
+    Loading, // assets load and the world around the spawn point is generated
+    SlotSelect, // once after starting: continue a saved game or play the new world
+    Playing,
+    Paused,
+    Error, // an asset failed, see `ErrorMessage`
+}
+
+/// Why the app is in `AppState::Error`
+#[derive(Resource, Default)]
+pub struct ErrorMessage(pub String);
+
+pub struct AppStatePlugin;
+
+impl Plugin for AppStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<AppState>();
+        app.init_resource::<ErrorMessage>();
+        app.add_systems(OnEnter(AppState::Loading), spawn_loading_text);
+        app.add_systems(Update, update_loading_text.run_if(in_state(AppState::Loading)));
+        app.add_systems(OnExit(AppState::Loading), despawn_with::<LoadingText>);
+        app.add_systems(OnEnter(AppState::Paused), (spawn_paused_text, pause_time));
+        app.add_systems(OnExit(AppState::Paused), (despawn_with::<PausedText>, unpause_time));
+        app.add_systems(OnEnter(AppState::Error), spawn_error_text);
+        app.add_systems(Update, update_error_text.run_if(in_state(AppState::Error)));
+        app.add_systems(Update, toggle_pause.run_if(in_state(AppState::Playing).or_else(in_state(AppState::Paused))));
+    }
+}
+
+#[derive(Component)]
+struct LoadingText;
+
+#[derive(Component)]
+struct PausedText;
+
+#[derive(Component)]
+struct ErrorText;
+
+fn screen_text(value: &str) -> TextBundle {
+    TextBundle::from_section(
+        value,
+        TextStyle {
+            font_size: 40.0,
+            ..Default::default()
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
+        top: Val::Percent(40.0),
+        left: Val::Percent(30.0),
+        ..Default::default()
+    })
+    .with_text_alignment(TextAlignment::Center)
+}
+
+fn spawn_loading_text(mut commands: Commands) {
+    commands.spawn((screen_text("Loading map..."), LoadingText));
+}
+
+fn spawn_paused_text(mut commands: Commands) {
+    commands.spawn((screen_text("Paused\n[Esc] to continue"), PausedText));
+}
+
+fn spawn_error_text(mut commands: Commands) {
+    commands.spawn((screen_text("Error"), ErrorText));
+}
+
+fn despawn_with<T: Component>(mut commands: Commands, entities: Query<Entity, With<T>>) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn update_loading_text(
+    tile_assets: Option<Res<TileAssets>>,
+    map_data: Res<MapData>,
+    mut texts: Query<&mut Text, With<LoadingText>>,
+) {
+    let Ok(mut text) = texts.get_single_mut() else { return };
+    text.sections[0].value = match tile_assets.and_then(|tile_assets| tile_assets.generation_progress(&map_data)) {
+        None => "Loading map...".to_string(),
+        Some(progress) => format!("Generating world... {:.0}%", progress * 100.0),
+    };
+}
+
+fn update_error_text(error_message: Res<ErrorMessage>, mut texts: Query<&mut Text, With<ErrorText>>) {
+    let Ok(mut text) = texts.get_single_mut() else { return };
+    text.sections[0].value = format!("Error\n{}", error_message.0);
+}
+
+fn toggle_pause(input: Res<Input<KeyCode>>, state: Res<State<AppState>>, mut next_state: ResMut<NextState<AppState>>) {
+    if input.just_pressed(KeyCode::Escape) {
+        next_state.set(match state.get() {
+            AppState::Paused => AppState::Playing,
+            _ => AppState::Paused,
+        });
+    }
+}
+
+// progress bars and animations use the virtual clock, so they stop too
+fn pause_time(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+fn unpause_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
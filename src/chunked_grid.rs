@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use bevy::math::IVec2;
+
+use crate::multi_vec::{Grid, MultiVec};
+
+/// A chunk was allocated or dropped, see `ChunkedGrid::take_chunk_events`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkEvent {
+    Loaded(IVec2),
+    Unloaded(IVec2),
+}
+
+/// Unbounded grid made of `chunk_size` x `chunk_size` `MultiVec`s that are allocated when a cell in them is written.
+/// New chunks are filled with `default`. Chunk (0, 0) starts at cell (0, 0).
+#[derive(Debug, Clone)]
+pub struct ChunkedGrid<T> {
+    chunk_size: usize,
+    default: T,
+    chunks: HashMap<IVec2, MultiVec<T>>,
+    events: Vec<ChunkEvent>,
+}
+
+impl<T: Clone> ChunkedGrid<T> {
+    pub fn new(chunk_size: usize, default: T) -> Self {
+        assert!(chunk_size > 0, "chunk size should not be 0");
+        ChunkedGrid { chunk_size, default, chunks: HashMap::new(), events: vec![] }
+    }
+
+    /// Chunk containing `pos` and the position inside of it
+    pub fn chunk_of(&self, pos: IVec2) -> (IVec2, (usize, usize)) {
+        let size = IVec2::splat(self.chunk_size as i32);
+        let inner = pos.rem_euclid(size);
+        (pos.div_euclid(size), (inner.x as usize, inner.y as usize))
+    }
+
+    /// first cell of the chunk
+    #[allow(dead_code)] // API for unbounded worlds, the map itself is still a fixed size `MultiVec`
+    pub fn chunk_origin(&self, chunk: IVec2) -> IVec2 {
+        chunk * self.chunk_size as i32
+    }
+
+    /// None if the chunk is not loaded
+    pub fn get(&self, pos: IVec2) -> Option<&T> {
+        let (chunk, (x, y)) = self.chunk_of(pos);
+        self.chunks.get(&chunk)?.get(x, y)
+    }
+
+    /// Loads the chunk if needed
+    pub fn get_mut(&mut self, pos: IVec2) -> &mut T {
+        let (chunk, (x, y)) = self.chunk_of(pos);
+        self.load_chunk(chunk).get_mut(x, y).expect("position inside the chunk")
+    }
+
+    /// None if the chunk is not loaded, unlike `get_mut`
+    pub fn get_loaded_mut(&mut self, pos: IVec2) -> Option<&mut T> {
+        let (chunk, (x, y)) = self.chunk_of(pos);
+        self.chunks.get_mut(&chunk)?.get_mut(x, y)
+    }
+
+    #[allow(dead_code)] // API for unbounded worlds
+    pub fn is_loaded(&self, chunk: IVec2) -> bool {
+        self.chunks.contains_key(&chunk)
+    }
+
+    /// Allocates the chunk unless it is loaded already
+    pub fn load_chunk(&mut self, chunk: IVec2) -> &mut MultiVec<T> {
+        let (size, default, events) = (self.chunk_size, &self.default, &mut self.events);
+        self.chunks.entry(chunk).or_insert_with(|| {
+            events.push(ChunkEvent::Loaded(chunk));
+            MultiVec::new(default.clone(), size, size)
+        })
+    }
+
+    /// Drops the chunk and returns its cells, None if it wasn't loaded
+    #[allow(dead_code)] // API for unbounded worlds
+    pub fn unload_chunk(&mut self, chunk: IVec2) -> Option<MultiVec<T>> {
+        let cells = self.chunks.remove(&chunk)?;
+        self.events.push(ChunkEvent::Unloaded(chunk));
+        Some(cells)
+    }
+
+    /// loaded chunks in no particular order
+    #[allow(dead_code)] // API for unbounded worlds
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec2, &MultiVec<T>)> {
+        self.chunks.iter().map(|(&chunk, cells)| (chunk, cells))
+    }
+
+    /// Chunks loaded and unloaded since the last call, in the order it happened
+    #[allow(dead_code)] // API for unbounded worlds
+    pub fn take_chunk_events(&mut self) -> Vec<ChunkEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+impl<T: Clone> Grid<T> for ChunkedGrid<T> {
+    fn cell(&self, pos: IVec2) -> Option<&T> {
+        self.get(pos)
+    }
+
+    /// loads the chunk, so this is never None
+    fn cell_mut(&mut self, pos: IVec2) -> Option<&mut T> {
+        Some(self.get_mut(pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_positions_map_to_their_own_chunks() {
+        let grid = ChunkedGrid::new(4, 0);
+        assert_eq!(grid.chunk_of(IVec2::new(0, 0)), (IVec2::new(0, 0), (0, 0)));
+        assert_eq!(grid.chunk_of(IVec2::new(3, 4)), (IVec2::new(0, 1), (3, 0)));
+        assert_eq!(grid.chunk_of(IVec2::new(-1, -4)), (IVec2::new(-1, -1), (3, 0)));
+        assert_eq!(grid.chunk_of(IVec2::new(-5, -9)), (IVec2::new(-2, -3), (3, 3)));
+        assert_eq!(grid.chunk_origin(IVec2::new(-2, 1)), IVec2::new(-8, 4));
+    }
+
+    #[test]
+    fn chunks_load_when_written() {
+        let mut grid = ChunkedGrid::new(4, 0);
+        assert_eq!(grid.get(IVec2::new(-1, 2)), None);
+        assert_eq!(grid.get_loaded_mut(IVec2::new(-1, 2)), None);
+        assert!(!grid.is_loaded(IVec2::new(-1, 0)));
+
+        *grid.cell_mut(IVec2::new(-1, 2)).unwrap() = 7;
+        assert!(grid.is_loaded(IVec2::new(-1, 0)));
+        assert_eq!(grid.cell(IVec2::new(-1, 2)), Some(&7));
+        // the rest of the chunk holds the default, other chunks stay unloaded
+        assert_eq!(grid.get(IVec2::new(-4, 0)), Some(&0));
+        assert_eq!(grid.get(IVec2::new(0, 2)), None);
+        assert_eq!(grid.chunks().count(), 1);
+    }
+
+    #[test]
+    fn chunk_events() {
+        let mut grid = ChunkedGrid::new(2, 'a');
+        *grid.get_mut(IVec2::new(0, 0)) = 'b';
+        *grid.get_mut(IVec2::new(1, 1)) = 'c';
+        *grid.get_mut(IVec2::new(-1, 0)) = 'd';
+        assert_eq!(grid.take_chunk_events(), [ChunkEvent::Loaded(IVec2::new(0, 0)), ChunkEvent::Loaded(IVec2::new(-1, 0))]);
+        assert_eq!(grid.take_chunk_events(), []);
+
+        let cells = grid.unload_chunk(IVec2::new(0, 0)).unwrap();
+        assert_eq!(cells.iter().collect::<String>(), "baac");
+        assert!(grid.unload_chunk(IVec2::new(0, 0)).is_none());
+        assert_eq!(grid.take_chunk_events(), [ChunkEvent::Unloaded(IVec2::new(0, 0))]);
+        assert_eq!(grid.get(IVec2::new(0, 0)), None);
+    }
+}
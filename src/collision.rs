@@ -0,0 +1,195 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    game_object::GameObject,
+    game_tile::{Corner, MapData},
+    tile_metadata::TileMetadata,
+    tile_pos::{TilePos, WorldPos},
+};
+
+/// distance kept to obstacles after a hit, so sliding along a wall doesn't touch it again
+const SKIN: f32 = 0.001;
+
+/// Hitbox of something that moves with `Collisions::move_and_slide`, centred on its translation
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Collider {
+    pub size: Vec2,
+}
+
+/// First contact of a swept box
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub time: f32, // fraction of the movement until the contact, 0..=1
+    pub normal: Vec2, // points away from the obstacle
+}
+
+/// Everything a moving box can bump into: quarter tiles that can't be entered, map edges and object hitboxes
+#[derive(SystemParam)]
+pub struct Collisions<'w, 's> {
+    map_data: Res<'w, MapData>,
+    metadata: Res<'w, TileMetadata>,
+    objects: Query<'w, 's, (&'static GameObject, &'static Transform), Without<Collider>>,
+}
+
+impl Collisions<'_, '_> {
+    /// Moves a box of `size` from `pos` by up to `delta`, sliding along whatever it hits. Returns the new position.
+    pub fn move_and_slide(&self, pos: Vec2, size: Vec2, delta: Vec2) -> Vec2 {
+        let reach = Rect::from_center_size(pos, size).union(Rect::from_center_size(pos + delta, size));
+        let obstacles = self.obstacles(reach);
+        move_and_slide(pos, size, delta, &obstacles)
+    }
+
+    /// Solid boxes touching `area`. Cells outside the map or not generated yet are solid as a whole.
+    pub fn obstacles(&self, area: Rect) -> Vec<Rect> {
+        let min = WorldPos(area.min).tile_pos();
+        let max = WorldPos(area.max).tile_pos();
+        let mut obstacles = vec![];
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let tile_pos = TilePos::new(x, y);
+                let centre = tile_pos.world_pos().0;
+                let Some(tile) = self.map_data.get_at(tile_pos) else {
+                    obstacles.push(Rect::from_center_size(centre, Vec2::ONE));
+                    continue;
+                };
+                for corner in Corner::CLOCKWISE {
+                    if !self.metadata.can_enter(&tile, corner).unwrap_or(true) {
+                        obstacles.push(Rect::from_center_size(centre + corner.direction() * 0.25, Vec2::splat(0.5)));
+                    }
+                }
+                for &entity in self.map_data.objects_at(tile_pos) {
+                    let Ok((object, transform)) = self.objects.get(entity) else { continue };
+                    if let Some(size) = object.hitbox() {
+                        obstacles.push(Rect::from_center_size(transform.translation.truncate(), size));
+                    }
+                }
+            }
+        }
+        obstacles
+    }
+}
+
+/// When a box of `size` moving from `pos` by `delta` first touches `obstacle`.
+/// None if it doesn't within this movement, or if it already overlaps the obstacle so it can get out again.
+pub fn sweep(pos: Vec2, size: Vec2, delta: Vec2, obstacle: Rect) -> Option<Hit> {
+    // grow the obstacle by the moving box, then it is a ray cast from the box centre
+    let min = obstacle.min - size / 2.0;
+    let max = obstacle.max + size / 2.0;
+    let mut entry = Vec2::NEG_INFINITY;
+    let mut exit = Vec2::INFINITY;
+    for axis in 0..2 {
+        if delta[axis] == 0.0 {
+            if pos[axis] <= min[axis] || pos[axis] >= max[axis] {
+                return None;
+            }
+        } else {
+            let t1 = (min[axis] - pos[axis]) / delta[axis];
+            let t2 = (max[axis] - pos[axis]) / delta[axis];
+            entry[axis] = t1.min(t2);
+            exit[axis] = t1.max(t2);
+        }
+    }
+    let time = entry.max_element();
+    if !(0.0..=1.0).contains(&time) || time >= exit.min_element() {
+        return None;
+    }
+    let normal = if entry.x > entry.y {
+        Vec2::new(-delta.x.signum(), 0.0)
+    } else {
+        Vec2::new(0.0, -delta.y.signum())
+    };
+    Some(Hit { time, normal })
+}
+
+/// Moves as far as possible, then continues with the part of the movement along the wall
+pub fn move_and_slide(mut pos: Vec2, size: Vec2, mut delta: Vec2, obstacles: &[Rect]) -> Vec2 {
+    // a corner needs two slides, more would only happen in a dead end
+    for _ in 0..3 {
+        if delta == Vec2::ZERO {
+            break;
+        }
+        let hit = obstacles.iter()
+            .filter_map(|obstacle| sweep(pos, size, delta, *obstacle))
+            .min_by(|a, b| a.time.total_cmp(&b.time));
+        let Some(hit) = hit else {
+            pos += delta;
+            break;
+        };
+        pos += delta * hit.time + hit.normal * SKIN;
+        delta *= 1.0 - hit.time;
+        delta -= hit.normal * delta.dot(hit.normal);
+    }
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> Rect {
+        Rect::new(min_x, min_y, max_x, max_y)
+    }
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(actual.distance(expected) < 0.01, "{actual} should be close to {expected}");
+    }
+
+    /// the box must never end up inside an obstacle
+    fn assert_outside(pos: Vec2, size: Vec2, obstacles: &[Rect]) {
+        let moved = Rect::from_center_size(pos, size);
+        for obstacle in obstacles {
+            assert!(moved.intersect(*obstacle).is_empty(), "{moved:?} overlaps {obstacle:?}");
+        }
+    }
+
+    #[test]
+    fn head_on_contact() {
+        let wall = rect(2.0, -0.5, 3.0, 0.5);
+        let hit = sweep(Vec2::ZERO, Vec2::ONE, Vec2::new(3.0, 0.0), wall).expect("should hit the wall");
+        assert_eq!(hit.time, 0.5);
+        assert_eq!(hit.normal, Vec2::new(-1.0, 0.0));
+
+        let pos = move_and_slide(Vec2::ZERO, Vec2::ONE, Vec2::new(3.0, 0.0), &[wall]);
+        assert_near(pos, Vec2::new(1.5, 0.0));
+        assert_outside(pos, Vec2::ONE, &[wall]);
+
+        assert!(sweep(Vec2::ZERO, Vec2::ONE, Vec2::new(1.0, 0.0), wall).is_none(), "stops short of the wall");
+        assert!(sweep(Vec2::ZERO, Vec2::ONE, Vec2::new(0.0, 3.0), wall).is_none(), "moves past it");
+        assert!(sweep(Vec2::new(2.0, 0.0), Vec2::ONE, Vec2::new(1.0, 0.0), wall).is_none(), "already inside");
+    }
+
+    #[test]
+    fn slides_along_a_wall() {
+        let wall = rect(1.0, -5.0, 2.0, 5.0);
+        let pos = move_and_slide(Vec2::ZERO, Vec2::ONE, Vec2::new(1.0, 1.0), &[wall]);
+        assert_near(pos, Vec2::new(0.5, 1.0));
+        assert_outside(pos, Vec2::ONE, &[wall]);
+
+        // two tiles side by side make one floor, the seam between them must not stop the box
+        let floor = [rect(-1.0, -1.0, 0.0, 0.0), rect(0.0, -1.0, 1.0, 0.0)];
+        let size = Vec2::splat(0.5);
+        let start = Vec2::new(-0.5, 0.25 + SKIN);
+        let pos = move_and_slide(start, size, Vec2::new(1.0, -0.1), &floor);
+        assert_near(pos, Vec2::new(0.5, 0.25));
+        assert_outside(pos, size, &floor);
+    }
+
+    #[test]
+    fn corners_dont_clip() {
+        // moving diagonally into an inner corner stops on both axes
+        let walls = [rect(1.0, -5.0, 2.0, 5.0), rect(-5.0, 1.0, 5.0, 2.0)];
+        let pos = move_and_slide(Vec2::ZERO, Vec2::ONE, Vec2::new(2.0, 3.0), &walls);
+        assert_near(pos, Vec2::new(0.5, 0.5));
+        assert_outside(pos, Vec2::ONE, &walls);
+
+        // exactly onto the corner of a block: stopped, not through it
+        let block = rect(1.0, 1.0, 2.0, 2.0);
+        let pos = move_and_slide(Vec2::ZERO, Vec2::ONE, Vec2::new(2.0, 2.0), &[block]);
+        assert_outside(pos, Vec2::ONE, &[block]);
+        assert!(pos.x < 1.0 || pos.y < 1.0, "{pos} went through the block");
+
+        // just past the corner the box keeps going
+        let pos = move_and_slide(Vec2::new(0.0, -0.1), Vec2::ONE, Vec2::new(2.0, 0.0), &[rect(1.0, 0.4, 2.0, 2.0)]);
+        assert_near(pos, Vec2::new(2.0, -0.1));
+    }
+}
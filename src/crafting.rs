@@ -1,9 +1,15 @@
-use bevy::{prelude::*, input::{keyboard::KeyboardInput, ButtonState}};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    player::{Inventory, Player},
+    app_state::AppState,
+    items::{ChangeReason, Inventory, ItemRegistry, PlayerInventory, ITEMS_PATH},
+    player::Player,
     progress::{self, BuildProgress},
-    game_tile::{GameTile, TileType, MapData},
+    replay::{GameClock, GameInput, TickSet},
+    game_tile::{Corner, MapData},
+    tile_metadata::TileMetadata,
+    tile_pos::{TilePos, WorldPos},
 };
 
 
@@ -12,37 +18,25 @@ pub struct CraftingPlugin;
 impl Plugin for CraftingPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, startup);
-        app.add_systems(Update, update);
+        app.add_systems(Update, update_recipe_text.run_if(in_state(AppState::Playing)));
+        app.add_systems(FixedUpdate, update.in_set(TickSet::Gameplay).run_if(in_state(AppState::Playing)));
+        #[cfg(feature = "cheat")]
+        app.add_systems(FixedUpdate, cheat_items.before(update).in_set(TickSet::Gameplay).run_if(in_state(AppState::Playing)));
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Buildable {
+    // the aliases read saves and recordings written before the rename
+    #[serde(alias = "Ship")]
     Ship,
+    #[serde(alias = "Campfire")]
     Campfire,
+    #[serde(alias = "House")]
     House,
 }
 
-pub fn crafting_price(buildable: Buildable) -> Inventory {
-    match buildable {
-        Buildable::Ship => Inventory {
-            wood: 100,
-            stone: 50,
-            weapons: 0,
-        },
-        Buildable::Campfire => Inventory {
-            wood: 30,
-            stone: 0,
-            weapons: 0,
-        },
-        Buildable::House => Inventory {
-            wood: 50,
-            stone: 20,
-            weapons: 0,
-        },
-    }
-}
-
 pub fn crafting_name (buildable: Buildable) -> &'static str {
     match buildable {
         Buildable::Ship => "Ship",
@@ -56,7 +50,7 @@ pub struct RecipeText;
 
 #[derive(Resource)]
 pub struct CraftingState {
-    recipe: Buildable,
+    pub recipe: Buildable,
 }
 
 fn startup(
@@ -81,39 +75,76 @@ fn startup(
     ));
 }
 
-fn update(
-    mut commands: Commands,
+/// The cells a building could go on
+#[derive(SystemParam)]
+struct BuildSites<'w, 's> {
+    map_data: Res<'w, MapData>,
+    tile_metadata: Res<'w, TileMetadata>,
+    builds: Query<'w, 's, &'static Transform, With<BuildProgress>>,
+}
+
+impl BuildSites<'_, '_> {
+    /// an object stands on the cell or something is being built there
+    fn is_occupied(&self, tile_pos: TilePos) -> bool {
+        self.map_data.is_occupied(tile_pos)
+            || self.builds.iter().any(|build| WorldPos::from(build.translation).tile_pos() == tile_pos)
+    }
+}
+
+/// What starting a build progress bar needs
+#[derive(SystemParam)]
+struct BuildStarter<'w, 's> {
+    commands: Commands<'w, 's>,
+    progress_stuff: Res<'w, progress::ProgressStuff>,
+    clock: Res<'w, GameClock>,
+}
+
+impl BuildStarter<'_, '_> {
+    fn start(&mut self, buildable: Buildable, price: Inventory, time_to_build: f32, tile_pos: TilePos) {
+        progress::start_build_progress(BuildProgress {
+            others: vec![],
+            price_inv: price,
+            start_time: self.clock.seconds(),
+            time_to_build,
+            buildable,
+        }, &mut self.commands, &self.progress_stuff, tile_pos.world_pos().0);
+    }
+}
+
+fn update_recipe_text(
     mut recipe_texts: Query<(&mut RecipeText, &mut Text)>,
-    mut crafting_state: ResMut<CraftingState>,
-    mut key_evr: EventReader<KeyboardInput>,
-    mut players: Query<(&mut Player, &Transform)>,
-    map_data: Res<MapData>,
-    tiles: Query<&GameTile>,
-    progress_stuff: Res<progress::ProgressStuff>,
-    time: Res<Time>,
+    crafting_state: Res<CraftingState>,
+    item_registry: Res<ItemRegistry>,
 ) {
+    let price_text = item_registry.recipe(crafting_state.recipe)
+        .map_or("no recipe".to_string(), |price| item_registry.describe(price));
     let mut recipe_text = recipe_texts.iter_mut().next().expect("no recipe text found");
-    recipe_text.1.sections[0].value = format!("[R] to build {} ({})\n[Q] next recipe", crafting_name(crafting_state.recipe), crafting_price(crafting_state.recipe).to_string());
-
-    let key_evr = key_evr.read().collect::<Vec<_>>();
+    recipe_text.1.sections[0].value = format!("[R] to build {} ({})\n[Q] next recipe", crafting_name(crafting_state.recipe), price_text);
+}
 
-    // Cheats: Number keys add resources
-    #[cfg(feature = "cheat")]
-    {
-        let (mut player, _) = players.iter_mut().next().expect("no player found");
-        if key_evr.iter().any(|ev| ev.state == ButtonState::Pressed && ev.key_code == Some(KeyCode::Key1)) {
-            player.inventory.wood += 10;
-        }
-        if key_evr.iter().any(|ev| ev.state == ButtonState::Pressed && ev.key_code == Some(KeyCode::Key2)) {
-            player.inventory.stone += 10;
-        }
-        if key_evr.iter().any(|ev| ev.state == ButtonState::Pressed && ev.key_code == Some(KeyCode::Key3)) {
-            player.inventory.weapons += 10;
+/// Cheats: Number keys add 10 of the first nine items
+#[cfg(feature = "cheat")]
+fn cheat_items(input: Res<GameInput>, item_registry: Res<ItemRegistry>, mut inventory: PlayerInventory) {
+    const KEYS: [KeyCode; 9] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5,
+                                KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9];
+    for (key, item) in KEYS.into_iter().zip(item_registry.iter()) {
+        if input.just_pressed(key) {
+            inventory.add(&Inventory::from_iter([(item.id.clone(), 10)]), ChangeReason::Cheat);
         }
     }
+}
 
+fn update(
+    mut builder: BuildStarter,
+    mut crafting_state: ResMut<CraftingState>,
+    input: Res<GameInput>,
+    players: Query<&Transform, With<Player>>,
+    mut inventory: PlayerInventory,
+    sites: BuildSites,
+    item_registry: Res<ItemRegistry>,
+) {
     // [Q] to change recipe
-    if key_evr.iter().any(|ev| ev.state == ButtonState::Pressed && ev.key_code == Some(KeyCode::Q)) {
+    if input.just_pressed(KeyCode::Q) {
         crafting_state.recipe = match crafting_state.recipe {
             Buildable::Ship => Buildable::Campfire,
             Buildable::Campfire => Buildable::House,
@@ -122,20 +153,25 @@ fn update(
     }
 
     // [R] to build
-    if key_evr.iter().any(|ev| ev.state == ButtonState::Pressed && ev.key_code == Some(KeyCode::R)) {
-        let (player, transform) = players.iter_mut().next().expect("no player found");
-        let price = crafting_price(crafting_state.recipe);
-        let Inventory { wood: wood_price, stone: stone_price, weapons: weapons_price } = price;
-        if player.inventory.wood >= wood_price && player.inventory.stone >= stone_price && player.inventory.weapons >= weapons_price {
-            let (x, y, tile) = map_data.get_tile_at_pos(transform.translation.truncate(), &tiles).expect("no tile found");
-            let _has_water = tile.bottom_left_type() == Some(TileType::Water)
-                || tile.bottom_right_type() == Some(TileType::Water)
-                || tile.top_left_type() == Some(TileType::Water)
-                || tile.top_right_type() == Some(TileType::Water);
-            let has_land = tile.bottom_left_type() == Some(TileType::Field)
-                || tile.bottom_right_type() == Some(TileType::Field)
-                || tile.top_left_type() == Some(TileType::Field)
-                || tile.top_right_type() == Some(TileType::Field);
+    if input.just_pressed(KeyCode::R) {
+        let transform = players.iter().next().expect("no player found");
+        let Some(price) = item_registry.recipe(crafting_state.recipe).cloned() else {
+            info!("{} has no recipe in {ITEMS_PATH}", crafting_name(crafting_state.recipe));
+            return;
+        };
+        let missing = inventory.get().missing(&price);
+        if missing.is_empty() {
+            let tile_pos = WorldPos::from(transform.translation).tile_pos();
+            let tile = sites.map_data.get_at(tile_pos).expect("no tile found");
+            if sites.is_occupied(tile_pos) {
+                info!("There is already something here");
+                return;
+            }
+            let corner_types = Corner::CLOCKWISE.map(|corner| sites.tile_metadata.corner_type(&tile, corner));
+            let has_terrain = |name: &str| sites.tile_metadata.terrain_by_name(name)
+                .is_some_and(|terrain| corner_types.contains(&Some(terrain)));
+            let _has_water = has_terrain("water");
+            let has_land = has_terrain("field");
 
             match crafting_state.recipe {
                 Buildable::Ship => {
@@ -143,14 +179,13 @@ fn update(
                 },
                 Buildable::Campfire => {
                     if has_land {
+                        // reserved until the build finishes, refunded if it is cancelled
+                        if let Err(err) = inventory.try_remove(&price, ChangeReason::Reserved) {
+                            error!("Could not reserve the price of the campfire: {err}");
+                            return;
+                        }
                         info!("You can now cook meat on the campfire");
-                        progress::start_build_progress(BuildProgress {
-                            others: vec![],
-                            price_inv: price,
-                            start_time: time.elapsed_seconds(),
-                            time_to_build: 5.0,
-                            buildable: Buildable::Campfire,
-                        }, &mut commands, progress_stuff, Vec2::new(x as f32, y as f32));
+                        builder.start(Buildable::Campfire, price, 5.0, tile_pos);
                     } else {
                         info!("You can only build a campfire on land");
                     }
@@ -160,7 +195,7 @@ fn update(
                 },
             };
         } else {
-            info!("Not enough resources to build {}", crafting_name(crafting_state.recipe));
+            info!("Not enough resources to build {}, missing {}", crafting_name(crafting_state.recipe), item_registry.describe(&missing));
         }
     }
 }
@@ -1,9 +1,27 @@
+use std::str::FromStr;
 use bevy::prelude::*;
-#[derive(Debug, Copy, Clone)]
+use serde::Deserialize;
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ObjectType {
     Tree, Ship, Stone, Campfire
 }
 
+impl FromStr for ObjectType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        use ObjectType::*;
+        match name.to_lowercase().as_str() {
+            "tree" => Ok(Tree),
+            "ship" => Ok(Ship),
+            "stone" => Ok(Stone),
+            "campfire" => Ok(Campfire),
+            _ => Err(format!("Unknown object type {name}")),
+        }
+    }
+}
+
 impl From<ObjectType> for GameObject {
     fn from(object_type: ObjectType) -> Self {
         use ObjectType::*;
@@ -33,5 +51,22 @@ impl GameObject {
             _  => None,
         }
     }
+
+    /// z of the sprite, buildings are drawn above the objects the world was generated with
+    pub fn z(&self) -> f32 {
+        match self.get_type() {
+            Some(ObjectType::Campfire) => 2.0,
+            _ => -0.5,
+        }
+    }
+
+    /// Size of the solid part, centred on the object. None for objects you can walk over.
+    pub fn hitbox(&self) -> Option<Vec2> {
+        match self.get_type()? {
+            ObjectType::Tree => Some(Vec2::new(0.3, 0.4)), // only the trunk
+            ObjectType::Stone => Some(Vec2::new(0.7, 0.5)),
+            ObjectType::Ship | ObjectType::Campfire => None,
+        }
+    }
 }
 
@@ -1,85 +1,230 @@
+use std::{collections::{HashMap, HashSet}, f32::consts::FRAC_PI_2};
 use bevy::prelude::*;
-use crate::multi_vec::MultiVec;
-#[derive(Component, Debug, Reflect, Clone, Copy)]
+use crate::{chunked_grid::ChunkedGrid, multi_vec::{Grid, MultiVec}, tile_chunks::CHUNK_SIZE, tile_pos::{TilePos, WorldPos}};
+#[derive(Debug, Reflect, Clone, Copy)]
 
 pub struct GameTile {
     pub tile_id: i32,
+    pub orientation: TileOrientation,
 }
 
+/// Orientation as stored by Pyxel Edit: the tile image is mirrored first, then rotated clockwise
+#[derive(Debug, Default, Reflect, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileOrientation {
+    pub flip_x: bool,
+    pub rot: u8, // clockwise quarter turns, 0..=3
+}
+
+/// Corners of a tile in clockwise order
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum TileType {
-    Water, Field, Mountain, Desert,
+pub enum Corner {
+    TopLeft, TopRight, BottomRight, BottomLeft,
 }
 
-#[derive(Default, Resource)]
-pub struct MapData(pub MultiVec<Option<Entity>>);
+/// Terrain at a tile corner, index into `TileMetadata::terrains`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TileType(pub usize);
+
+/// Terrain of every map cell, None until generated. Tiles are no entities, they are drawn in chunks by `tile_chunks`.
+/// Also knows which object entities stand on which cell, kept up to date by `tile_world::track_objects`.
+/// Objects are kept in a `ChunkedGrid`, they can stand outside of the generated terrain.
+#[derive(Resource)]
+pub struct MapData {
+    tiles: MultiVec<Option<GameTile>>,
+    dirty_chunks: HashSet<(usize, usize)>,
+    objects: ChunkedGrid<Vec<Entity>>,
+    object_cells: HashMap<Entity, TilePos>,
+}
+
+impl Default for MapData {
+    fn default() -> Self {
+        MapData::new(0, 0)
+    }
+}
 
 impl MapData {    
-    pub fn get_tile_at_pos(&self, pos: Vec2, tiles: &Query<&GameTile>) -> Option<(usize, usize, GameTile)> {
-        let pos = pos.round();
-        if pos.x < -0.5 {return None}
-        let x = pos.x as usize;
-        if pos.y < -0.5 {return None}
-        let y = pos.y as usize;
-        let entity = self.0.get(x, y)?.as_ref()?;
-        Some((x, y, *tiles.get_component::<GameTile>(*entity).ok()?))
+    pub fn new(w: usize, h: usize) -> Self {
+        MapData {
+            tiles: MultiVec::new(None, w, h),
+            dirty_chunks: HashSet::new(),
+            objects: ChunkedGrid::new(CHUNK_SIZE, vec![]),
+            object_cells: HashMap::new(),
+        }
+    }
+
+    pub fn tiles(&self) -> &MultiVec<Option<GameTile>> {
+        &self.tiles
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<GameTile> {
+        self.get_at(TilePos::from_index(x, y))
+    }
+
+    /// panics if (x, y) is outside of the map
+    pub fn set(&mut self, x: usize, y: usize, tile: GameTile) {
+        *self.tiles.cell_mut(TilePos::from_index(x, y).into()).expect("tile position should be inside the map") = Some(tile);
+        self.dirty_chunks.insert((x / CHUNK_SIZE, y / CHUNK_SIZE));
+    }
+
+    /// chunks changed since the last call
+    pub fn take_dirty_chunks(&mut self) -> HashSet<(usize, usize)> {
+        std::mem::take(&mut self.dirty_chunks)
+    }
+
+    /// None outside of the map and for cells that are not generated yet
+    pub fn get_at(&self, pos: TilePos) -> Option<GameTile> {
+        *self.tiles.cell(pos.into())?
+    }
+
+    /// objects standing on the cell, in the order they were placed
+    pub fn objects_at(&self, pos: TilePos) -> &[Entity] {
+        self.objects.cell(pos.into()).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_occupied(&self, pos: TilePos) -> bool {
+        !self.objects_at(pos).is_empty()
+    }
+
+    /// objects on cells whose centre is at most `radius` away from `centre`
+    pub fn objects_in_radius(&self, centre: WorldPos, radius: f32) -> Vec<Entity> {
+        let reach = radius.ceil() as i32;
+        let centre_tile = centre.tile_pos();
+        let mut found = vec![];
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let pos = centre_tile.offset(dx, dy);
+                if pos.world_pos().0.distance(centre.0) <= radius {
+                    found.extend_from_slice(self.objects_at(pos));
+                }
+            }
+        }
+        found
+    }
+
+    /// Moves `entity` to `pos`, or adds it if it is not on the map yet
+    pub fn place_object(&mut self, entity: Entity, pos: TilePos) {
+        if self.object_cells.get(&entity) == Some(&pos) {
+            return;
+        }
+        self.remove_object(entity);
+        self.objects.get_mut(pos.into()).push(entity);
+        self.object_cells.insert(entity, pos);
+    }
+
+    /// does nothing for entities that are not on the map
+    pub fn remove_object(&mut self, entity: Entity) {
+        let Some(pos) = self.object_cells.remove(&entity) else { return };
+        if let Some(entities) = self.objects.get_loaded_mut(pos.into()) {
+            entities.retain(|&other| other != entity);
+        }
     }
 }
 
-impl TileType {
-    pub fn can_enter(self) -> bool{
+impl Corner {
+    pub const CLOCKWISE: [Corner; 4] = [Corner::TopLeft, Corner::TopRight, Corner::BottomRight, Corner::BottomLeft];
+
+    /// direction from the tile centre towards the corner, y pointing up
+    pub fn direction(self) -> Vec2 {
+        match self {
+            Corner::TopLeft     => Vec2::new(-1.0,  1.0),
+            Corner::TopRight    => Vec2::new( 1.0,  1.0),
+            Corner::BottomRight => Vec2::new( 1.0, -1.0),
+            Corner::BottomLeft  => Vec2::new(-1.0, -1.0),
+        }
+    }
+
+    fn mirrored_x(self) -> Corner {
         match self {
-            TileType::Field    => true,
-            TileType::Desert   => true,
-            TileType::Water    => false,
-            TileType::Mountain => false,
+            Corner::TopLeft     => Corner::TopRight,
+            Corner::TopRight    => Corner::TopLeft,
+            Corner::BottomRight => Corner::BottomLeft,
+            Corner::BottomLeft  => Corner::BottomRight,
         }
     }
 }
 
+impl TileOrientation {
+    /// The corner of the unrotated tile image that ends up at `corner` after applying this orientation
+    pub fn source_corner(self, corner: Corner) -> Corner {
+        let unrotated = Corner::CLOCKWISE[(corner as usize + 4 - self.rot as usize % 4) % 4];
+        if self.flip_x { unrotated.mirrored_x() } else { unrotated }
+    }
+
+    pub fn rotation(self) -> Quat {
+        Quat::from_rotation_z(-f32::from(self.rot % 4) * FRAC_PI_2)
+    }
+
+    fn to_bits(self) -> i32 {
+        (self.rot % 4) as i32 * 2 + self.flip_x as i32
+    }
+
+    fn from_bits(bits: i32) -> Self {
+        TileOrientation { flip_x: bits % 2 == 1, rot: (bits / 2) as u8 }
+    }
+}
+
 impl GameTile {
-    pub fn top_left_type(&self) -> Option<TileType> {
-        use TileType::*;
-        match self.tile_id {
-             0| 1| 2| 3| 8|11|15                                        => Some(Water),
-             4| 5| 6| 7| 9|10|14|16|17|18|19|24|31|32|33|34|35|40|47|57 => Some(Field),
-            20|21|22|23|25|26|30|43|44|45|48|51|56|59|63                => Some(Mountain),
-            36|37|38|39|41|42|46|49|53|54|55|60|61|62                   => Some(Desert),
-            _                                                           => None,
-        }
+    /// Tile id with the orientation folded in, so the generator can treat rotated tiles as tiles of their own.
+    /// -1 (empty) stays -1.
+    pub fn to_packed(self) -> i32 {
+        if self.tile_id < 0 { -1 } else { self.tile_id * 8 + self.orientation.to_bits() }
     }
-    
-    pub fn top_right_type(&self) -> Option<TileType> {
-        use TileType::*;
-        match self.tile_id {
-             0| 1| 2| 5|10|11|14                                        => Some(Water),
-             3| 4| 6| 7| 8| 9|15|16|17|18|21|26|30|32|33|34|37|42|46|56 => Some(Field),
-            19|20|22|23|24|25|31|43|44|45|49|53|57|61|62                => Some(Mountain),
-            35|36|38|39|40|41|47|48|51|54|55|59|60|63                   => Some(Desert),
-            _                                                           => None,
+
+    pub fn from_packed(packed: i32) -> Self {
+        if packed < 0 {
+            GameTile { tile_id: -1, orientation: default() }
+        } else {
+            GameTile { tile_id: packed / 8, orientation: TileOrientation::from_bits(packed % 8) }
         }
     }
-    pub fn bottom_left_type(&self) -> Option<TileType> {
-        use TileType::*;
-        match self.tile_id {
-             0| 3| 4| 5| 7| 8|11                                        => Some(Water),
-             1| 2| 6| 9|10|14|15|16|19|20|21|23|24|32|35|36|37|39|40|49 => Some(Field),
-            17|18|22|25|26|30|31|43|48|51|55|56|59|60|61                => Some(Mountain),
-            33|34|38|41|42|44|45|46|47|53|54|57|52|63                   => Some(Desert),
-            _                                                           => None,
-        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orientations() -> impl Iterator<Item = TileOrientation> {
+        (0..4).flat_map(|rot| [false, true].map(|flip_x| TileOrientation { flip_x, rot }))
     }
-    
-    pub fn bottom_right_type(&self) -> Option<TileType> {
-        use TileType::*;
-        match self.tile_id {
-             2| 3| 4| 5| 6|10|11                                        => Some(Water),
-             0| 1| 7| 8| 9|14|15|18|19|20|21|22|26|34|35|36|37|38|42|48 => Some(Field),
-            16|17|23|24|25|30|31|45|49|53|54|57|59|60|61                => Some(Mountain),
-            32|33|39|40|41|43|44|46|47|51|55|56|62|63                   => Some(Desert),
-            _                                                           => None,
+
+    #[test]
+    fn packed_ids_round_trip() {
+        let mut packed_ids = HashSet::new();
+        for orientation in orientations() {
+            for tile_id in [0, 5, 63] {
+                let tile = GameTile { tile_id, orientation };
+                let packed = tile.to_packed();
+                assert!(packed_ids.insert(packed), "{tile:?} shares its packed id");
+                let unpacked = GameTile::from_packed(packed);
+                assert_eq!((unpacked.tile_id, unpacked.orientation), (tile_id, orientation));
+            }
         }
+        assert_eq!(GameTile { tile_id: -1, orientation: TileOrientation { flip_x: true, rot: 3 } }.to_packed(), -1);
+        assert_eq!(GameTile::from_packed(-1).tile_id, -1);
     }
-}
 
+    #[test]
+    fn source_corners_match_the_rotation() {
+        // the image is mirrored first, then rotated, so undo the rotation first, then the mirroring
+        for orientation in orientations() {
+            for corner in Corner::CLOCKWISE {
+                let unrotated = orientation.rotation().inverse() * corner.direction().extend(0.0);
+                let source = if orientation.flip_x { Vec2::new(-unrotated.x, unrotated.y) } else { unrotated.truncate() };
+                let expected = Corner::CLOCKWISE.into_iter()
+                    .find(|candidate| candidate.direction().distance(source) < 1e-4)
+                    .unwrap();
+                assert_eq!(orientation.source_corner(corner), expected, "{corner:?} of {orientation:?}");
+            }
+        }
 
+        let quarter_turn = TileOrientation { flip_x: false, rot: 1 };
+        assert_eq!(quarter_turn.source_corner(Corner::TopRight), Corner::TopLeft);
+        assert_eq!(quarter_turn.source_corner(Corner::TopLeft), Corner::BottomLeft);
+        let mirrored = TileOrientation { flip_x: true, rot: 0 };
+        assert_eq!(mirrored.source_corner(Corner::TopLeft), Corner::TopRight);
+        assert_eq!(mirrored.source_corner(Corner::BottomRight), Corner::BottomLeft);
+        let mirrored_quarter_turn = TileOrientation { flip_x: true, rot: 1 };
+        assert_eq!(mirrored_quarter_turn.source_corner(Corner::TopRight), Corner::TopRight);
+        assert_eq!(mirrored_quarter_turn.source_corner(Corner::TopLeft), Corner::BottomRight);
+    }
+}
@@ -0,0 +1,439 @@
+use std::{collections::{BTreeMap, HashMap}, fmt::{Display, Formatter}, ops::AddAssign};
+
+use bevy::{asset::LoadState, ecs::system::SystemParam, prelude::*};
+use bevy_common_assets::json::JsonAssetPlugin;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::{AppState, ErrorMessage},
+    crafting::Buildable,
+    game_object::ObjectType,
+    player::Player,
+};
+
+pub const ITEMS_PATH: &str = "items.json";
+
+pub struct ItemsPlugin;
+
+impl Plugin for ItemsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(JsonAssetPlugin::<ItemsFile>::new(&["items.json"]));
+        app.init_resource::<ItemRegistry>();
+        app.add_event::<InventoryChanged>();
+        app.add_systems(PreStartup, load_items);
+        app.add_systems(PreUpdate, (
+            update_registry,
+            fail_on_missing_items.run_if(in_state(AppState::Loading)),
+        ));
+        app.add_systems(Last, log_inventory_changes);
+    }
+}
+
+/// `items.json`: everything the player can carry, what objects drop and what buildings cost.
+/// New items only need an entry here.
+#[derive(Deserialize, Asset, TypePath)]
+pub struct ItemsFile {
+    items: Vec<Item>,
+    #[serde(default)]
+    drops: HashMap<ObjectType, Inventory>, // objects without drops can't be destroyed
+    #[serde(default)]
+    recipes: HashMap<Buildable, Inventory>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Item {
+    pub id: ItemId,
+    pub name: String, // shown to the player
+    #[allow(dead_code)] // for the inventory screen
+    pub icon: usize, // index in the item icon atlas
+    #[serde(default = "default_stack_size")]
+    pub stack_size: u32, // the player can't carry more of it
+    #[serde(default)]
+    #[allow(dead_code)] // e.g. "fuel" or "food", for recipes that take any item of a kind
+    pub tags: Vec<String>,
+}
+
+fn default_stack_size() -> u32 {
+    99
+}
+
+/// Key of an item in `items.json`, e.g. "wood"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct ItemId(pub String);
+
+impl From<&str> for ItemId {
+    fn from(id: &str) -> Self {
+        ItemId(id.to_string())
+    }
+}
+
+impl Display for ItemId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The items of `items.json` in file order, empty until it is loaded
+#[derive(Resource, Default)]
+pub struct ItemRegistry {
+    items: Vec<Item>,
+    by_id: HashMap<ItemId, usize>,
+    drops: HashMap<ObjectType, Inventory>,
+    recipes: HashMap<Buildable, Inventory>,
+    loaded: bool,
+}
+
+impl ItemRegistry {
+    pub fn new(file: &ItemsFile) -> Self {
+        let mut registry = ItemRegistry {
+            items: vec![],
+            by_id: HashMap::new(),
+            drops: file.drops.clone(),
+            recipes: file.recipes.clone(),
+            loaded: true,
+        };
+        for item in &file.items {
+            if registry.by_id.contains_key(&item.id) {
+                warn!("{ITEMS_PATH}: item {} is defined twice, using the first one", item.id);
+                continue;
+            }
+            registry.by_id.insert(item.id.clone(), registry.items.len());
+            registry.items.push(item.clone());
+        }
+        let used = registry.drops.values().chain(registry.recipes.values()).flat_map(Inventory::iter);
+        for (id, _) in used {
+            if registry.get(id).is_none() {
+                warn!("{ITEMS_PATH}: drops or recipes use the unknown item {id}");
+            }
+        }
+        for (buildable, price) in &registry.recipes {
+            for (id, count) in price.iter() {
+                if count > registry.stack_size(id) {
+                    warn!("{ITEMS_PATH}: {buildable:?} needs {count} {id}, more than fit into the inventory");
+                }
+            }
+        }
+        registry
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    pub fn get(&self, id: &ItemId) -> Option<&Item> {
+        self.by_id.get(id).map(|&index| &self.items[index])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter()
+    }
+
+    /// What destroying the object gives, None if it can't be destroyed
+    pub fn drops(&self, object_type: ObjectType) -> Option<&Inventory> {
+        self.drops.get(&object_type)
+    }
+
+    /// What building it costs, None if there is no recipe for it
+    pub fn recipe(&self, buildable: Buildable) -> Option<&Inventory> {
+        self.recipes.get(&buildable)
+    }
+
+    /// How many the player can carry, no limit for items that are not in the registry
+    pub fn stack_size(&self, id: &ItemId) -> u32 {
+        self.get(id).map_or(u32::MAX, |item| item.stack_size)
+    }
+
+    /// Display name, the id for items that are not in the registry
+    pub fn name<'a>(&'a self, id: &'a ItemId) -> &'a str {
+        self.get(id).map_or(&id.0, |item| &item.name)
+    }
+
+    /// e.g. "100 Wood, 50 Stone"
+    pub fn describe(&self, inventory: &Inventory) -> String {
+        inventory.iter()
+            .map(|(id, count)| format!("{} {}", count, self.name(id)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// How many of each item, items without any are left out. Counts can't go below 0,
+/// items only come out through `try_remove`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<ItemId, u32>", into = "BTreeMap<ItemId, u32>")]
+pub struct Inventory(BTreeMap<ItemId, u32>);
+
+#[derive(Debug, Display)]
+pub enum InventoryError {
+    #[display(fmt = "missing {_0}")]
+    Missing(Inventory),
+}
+
+impl std::error::Error for InventoryError {}
+
+impl Inventory {
+    pub fn count(&self, id: &ItemId) -> u32 {
+        self.0.get(id).copied().unwrap_or(0)
+    }
+
+    /// Adds as many as fit below `stack_size`, returns how many did not fit
+    pub fn add(&mut self, id: impl Into<ItemId>, count: u32, stack_size: u32) -> u32 {
+        let id = id.into();
+        let current = self.count(&id);
+        let added = count.min(stack_size.saturating_sub(current));
+        if added > 0 {
+            self.0.insert(id, current + added);
+        }
+        count - added
+    }
+
+    /// What `other` has more of than this inventory, empty if it contains all of `other`
+    pub fn missing(&self, other: &Inventory) -> Inventory {
+        other.iter()
+            .map(|(id, count)| (id.clone(), count.saturating_sub(self.count(id))))
+            .collect()
+    }
+
+    /// Takes out all of `items`, or nothing if some are missing
+    pub fn try_remove(&mut self, items: &Inventory) -> Result<(), InventoryError> {
+        let missing = self.missing(items);
+        if !missing.is_empty() {
+            return Err(InventoryError::Missing(missing));
+        }
+        for (id, count) in items.iter() {
+            let left = self.count(id) - count;
+            if left == 0 {
+                self.0.remove(id);
+            } else {
+                self.0.insert(id.clone(), left);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ItemId, u32)> {
+        self.0.iter().map(|(id, &count)| (id, count))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<I: Into<ItemId>> FromIterator<(I, u32)> for Inventory {
+    fn from_iter<T: IntoIterator<Item = (I, u32)>>(iter: T) -> Self {
+        let mut inventory = Inventory::default();
+        for (id, count) in iter {
+            inventory.add(id, count, u32::MAX);
+        }
+        inventory
+    }
+}
+
+// older saves list every item, also those with 0
+impl From<BTreeMap<ItemId, u32>> for Inventory {
+    fn from(counts: BTreeMap<ItemId, u32>) -> Self {
+        counts.into_iter().collect()
+    }
+}
+
+impl From<Inventory> for BTreeMap<ItemId, u32> {
+    fn from(inventory: Inventory) -> Self {
+        inventory.0
+    }
+}
+
+impl AddAssign<&Inventory> for Inventory {
+    fn add_assign(&mut self, other: &Inventory) {
+        for (id, count) in other.iter() {
+            self.add(id.clone(), count, u32::MAX); // sums like prices are no inventory the player carries
+        }
+    }
+}
+
+/// Item ids without the registry, e.g. "100 wood, 50 stone"
+impl Display for Inventory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.iter()
+            .map(|(id, count)| format!("{count} {id}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+            .fmt(f)
+    }
+}
+
+/// Why an inventory changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeReason {
+    Gathered,
+    Reserved, // taken for a build that is still in progress
+    Refunded, // the build was cancelled
+    Loaded,
+    #[cfg(feature = "cheat")]
+    Cheat,
+}
+
+/// Sent for every item whose count in the player's inventory changed
+#[derive(Event, Debug, Clone)]
+pub struct InventoryChanged {
+    pub item: ItemId,
+    pub delta: i64,
+    pub count: u32, // after the change
+    pub reason: ChangeReason,
+}
+
+/// The player's inventory. Changes go through here, so each one sends `InventoryChanged`.
+#[derive(SystemParam)]
+pub struct PlayerInventory<'w, 's> {
+    players: Query<'w, 's, &'static mut Player>,
+    events: EventWriter<'w, InventoryChanged>,
+    registry: Res<'w, ItemRegistry>,
+}
+
+impl PlayerInventory<'_, '_> {
+    pub fn get(&self) -> &Inventory {
+        &self.players.single().inventory
+    }
+
+    /// Items beyond their stack size are lost
+    pub fn add(&mut self, items: &Inventory, reason: ChangeReason) {
+        let mut new = self.get().clone();
+        for (id, count) in items.iter() {
+            let lost = new.add(id.clone(), count, self.registry.stack_size(id));
+            if lost > 0 {
+                info!("inventory full, {lost} {id} did not fit");
+            }
+        }
+        self.replace(new, reason);
+    }
+
+    pub fn try_remove(&mut self, items: &Inventory, reason: ChangeReason) -> Result<(), InventoryError> {
+        let mut new = self.get().clone();
+        new.try_remove(items)?;
+        self.replace(new, reason);
+        Ok(())
+    }
+
+    /// Swaps the whole inventory, e.g. for a loaded game
+    pub fn replace(&mut self, inventory: Inventory, reason: ChangeReason) {
+        let mut player = self.players.single_mut();
+        let old = std::mem::replace(&mut player.inventory, inventory);
+        let mut items = old.0.keys().chain(player.inventory.0.keys()).collect::<Vec<_>>();
+        items.sort();
+        items.dedup();
+        for item in items {
+            let count = player.inventory.count(item);
+            let delta = count as i64 - old.count(item) as i64;
+            if delta != 0 {
+                self.events.send(InventoryChanged { item: item.clone(), delta, count, reason });
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ItemAssets {
+    file: Handle<ItemsFile>,
+}
+
+fn load_items(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ItemAssets { file: asset_server.load(ITEMS_PATH) });
+}
+
+/// Builds the registry once `items.json` is loaded, and again whenever it changes
+fn update_registry(
+    mut events: EventReader<AssetEvent<ItemsFile>>,
+    item_assets: Res<ItemAssets>,
+    items_files: Res<Assets<ItemsFile>>,
+    mut registry: ResMut<ItemRegistry>,
+) {
+    let changed = events.read().any(|event| match event {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => *id == item_assets.file.id(),
+        _ => false,
+    });
+    if !changed {
+        return;
+    }
+    let Some(file) = items_files.get(&item_assets.file) else { return };
+    *registry = ItemRegistry::new(file);
+    info!("loaded {} items", registry.items.len());
+}
+
+fn fail_on_missing_items(
+    asset_server: Res<AssetServer>,
+    item_assets: Res<ItemAssets>,
+    mut error_message: ResMut<ErrorMessage>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if asset_server.get_load_state(&item_assets.file) == Some(LoadState::Failed) {
+        error_message.0 = format!("Could not load {ITEMS_PATH}, see the log for details");
+        next_state.set(AppState::Error);
+    }
+}
+
+fn log_inventory_changes(mut events: EventReader<InventoryChanged>) {
+    for event in events.read() {
+        debug!("inventory: {:+} {} ({} now, {:?})", event.delta, event.item, event.count, event.reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    fn shipped_items_file() {
+        let file: ItemsFile = serde_json::from_str(include_str!("../assets/items.json")).unwrap();
+        let registry = ItemRegistry::new(&file);
+        assert_eq!(registry.name(&"wood".into()), "Wood");
+        assert_eq!(registry.drops(ObjectType::Tree), Some(&Inventory::from_iter([("wood", 10)])));
+        assert_eq!(registry.drops(ObjectType::Campfire), None);
+        assert_eq!(registry.recipe(Buildable::Campfire), Some(&Inventory::from_iter([("wood", 30)])));
+        for buildable in [Buildable::Ship, Buildable::Campfire, Buildable::House] {
+            assert!(registry.recipe(buildable).is_some(), "{buildable:?} should have a recipe");
+        }
+        let used = file.drops.values().chain(file.recipes.values()).flat_map(Inventory::iter);
+        for (id, _) in used {
+            assert!(registry.get(id).is_some(), "{id} should be defined");
+        }
+        assert_eq!(registry.stack_size(&"weapons".into()), 10);
+        for (buildable, price) in &file.recipes {
+            for (id, count) in price.iter() {
+                assert!(count <= registry.stack_size(id), "{buildable:?} needs more {id} than fit into the inventory");
+            }
+        }
+    }
+
+    #[test]
+    fn add_stops_at_the_stack_size() {
+        let mut inventory = Inventory::default();
+        assert_eq!(inventory.add("wood", 30, 50), 0);
+        assert_eq!(inventory.add("wood", 30, 50), 10);
+        assert_eq!(inventory.count(&"wood".into()), 50);
+        assert_eq!(inventory.add("wood", 5, 50), 5);
+        // nothing is taken away when the stack size is lowered
+        assert_eq!(inventory.add("wood", 1, 20), 1);
+        assert_eq!(inventory.count(&"wood".into()), 50);
+        // nothing fits, so no empty entry
+        assert_eq!(inventory.add("stone", 3, 0), 3);
+        assert!(!inventory.iter().any(|(id, _)| id.0 == "stone"));
+    }
+
+    #[test]
+    fn player_inventory_add_uses_the_registry() {
+        let file: ItemsFile = serde_json::from_str(r#"{ "items": [{ "id": "wood", "name": "Wood", "icon": 0, "stack_size": 50 }] }"#).unwrap();
+        let mut world = World::new();
+        world.init_resource::<Events<InventoryChanged>>();
+        world.insert_resource(ItemRegistry::new(&file));
+        world.spawn(Player::new());
+        world.run_system_once(|mut inventory: PlayerInventory| {
+            inventory.add(&Inventory::from_iter([("wood", 40), ("rope", 500)]), ChangeReason::Gathered);
+            inventory.add(&Inventory::from_iter([("wood", 40)]), ChangeReason::Gathered);
+            assert_eq!(inventory.get(), &Inventory::from_iter([("wood", 50), ("rope", 500)]));
+        });
+    }
+}
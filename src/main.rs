@@ -3,21 +3,48 @@
 
 mod game_object;
 mod game_tile;
+mod map_source;
 mod player;
+mod player_spawn;
 
-use bevy::{log::LogPlugin, prelude::*};
+use std::time::Duration;
+
+use bevy::{asset::io::AssetSource, log::LogPlugin, prelude::*};
+use app_state::AppStatePlugin;
 use crafting::CraftingPlugin;
+use items::ItemsPlugin;
 use object_interaction::ObjectInteractionPlugin;
 use progress::ProgressPlugin;
+use replay::ReplayPlugin;
+use save_game::SaveGamePlugin;
+use save_slots::SaveSlotsPlugin;
+use terraform::TerraformPlugin;
+use tile_chunks::TileChunksPlugin;
 
 use crate::player::PlayerPlugin;
 use crate::tile_world::TileWorldPlugin;
 
+mod app_state;
+mod chunked_grid;
+mod collision;
 mod crafting;
+mod items;
 mod multi_vec;
 mod object_interaction;
+mod pathfinding;
 mod progress;
+mod pyxel_file;
+mod replay;
+mod save_game;
+mod save_slots;
+mod terraform;
+mod terrain_cost;
+mod tile_chunks;
+mod tile_metadata;
+mod tile_pos;
 mod tile_world;
+mod tileset_validation;
+mod tiled_map;
 mod wave_function_collapse_generator;
 
 #[cfg(feature = "inspect")]
@@ -36,6 +63,10 @@ fn main() {
     println!("Use Log level: {}", log_level);
 
     let mut app = App::new();
+    // designers save straight into pyxel/, load from there via "pyxel://"
+    app.register_asset_source("pyxel", AssetSource::build()
+        .with_reader(AssetSource::get_default_reader("pyxel".to_string()))
+        .with_watcher(AssetSource::get_default_watcher("pyxel".to_string(), Duration::from_millis(300))));
     app.add_plugins(
         DefaultPlugins
             .set(ImagePlugin::default_nearest()) // prevents blurry sprites
@@ -48,11 +79,18 @@ fn main() {
     #[cfg(feature = "inspect")]
     app.add_plugins(WorldInspectorPlugin::new());
 
-    app.add_plugins(PlayerPlugin)
+    app.add_plugins(AppStatePlugin)
+        .add_plugins(ReplayPlugin)
+        .add_plugins(ItemsPlugin)
+        .add_plugins(PlayerPlugin)
         .add_plugins(TileWorldPlugin)
+        .add_plugins(TileChunksPlugin)
         .add_plugins(ProgressPlugin)
         .add_plugins(CraftingPlugin)
-        .add_plugins(ObjectInteractionPlugin);
+        .add_plugins(TerraformPlugin)
+        .add_plugins(ObjectInteractionPlugin)
+        .add_plugins(SaveGamePlugin)
+        .add_plugins(SaveSlotsPlugin);
 
     app.run();
 }
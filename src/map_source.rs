@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use bevy::{prelude::*, asset::UntypedAssetId};
+
+use crate::{
+    game_object::GameObject,
+    game_tile::GameTile,
+    pyxel_file::PyxelFile,
+    tiled_map::TiledMap,
+};
+
+/// A terrain tile placed in an editor, `y` pointing down like in the editors
+pub struct MapTile {
+    pub x: i32,
+    pub y: i32,
+    pub tile: GameTile,
+}
+
+/// An object placed in an editor, `x`/`y` is the tile it stands on
+pub struct MapObject {
+    pub x: i32,
+    pub y: i32,
+    pub object: GameObject,
+}
+
+/// Custom property of a tile or object, as edited in Tiled
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapProperty {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+pub type Properties = HashMap<String, MapProperty>;
+
+/// Everything the world generation needs from a map, no matter which editor produced it
+pub trait MapSource {
+    /// size of a single tile in pixels
+    fn tile_size(&self) -> Vec2;
+    fn tileset(&self) -> Handle<Image>;
+    /// columns and rows of tiles in the tileset image
+    fn atlas_grid(&self) -> (usize, usize);
+    fn base_tiles(&self) -> Vec<MapTile>;
+    fn objects(&self) -> Vec<MapObject>;
+    fn tile_properties(&self, tile_id: i32) -> Option<&Properties>;
+}
+
+/// Handle to a map asset of any supported format, picked by file extension
+#[derive(Clone)]
+pub enum MapHandle {
+    Pyxel(Handle<PyxelFile>),
+    Tiled(Handle<TiledMap>),
+}
+
+impl MapHandle {
+    pub fn load(asset_server: &AssetServer, path: &str) -> Self {
+        if path.ends_with(".tmx") || path.ends_with(".tmj") {
+            MapHandle::Tiled(asset_server.load(path.to_string()))
+        } else {
+            MapHandle::Pyxel(asset_server.load(path.to_string()))
+        }
+    }
+
+    pub fn id(&self) -> UntypedAssetId {
+        match self {
+            MapHandle::Pyxel(handle) => handle.id().untyped(),
+            MapHandle::Tiled(handle) => handle.id().untyped(),
+        }
+    }
+
+    pub fn get<'a>(&self, pyxel_files: &'a Assets<PyxelFile>, tiled_maps: &'a Assets<TiledMap>) -> Option<&'a dyn MapSource> {
+        match self {
+            MapHandle::Pyxel(handle) => pyxel_files.get(handle).map(|map| map as &dyn MapSource),
+            MapHandle::Tiled(handle) => tiled_maps.get(handle).map(|map| map as &dyn MapSource),
+        }
+    }
+}
+
+/// Hand-built map for tests, `tiles` and `objects` are (x, y, tile)
+#[cfg(test)]
+#[derive(Default)]
+pub struct TestMap {
+    pub tiles: Vec<(i32, i32, GameTile)>,
+    pub objects: Vec<(i32, i32, i32)>,
+}
+
+#[cfg(test)]
+impl MapSource for TestMap {
+    fn tile_size(&self) -> Vec2 {
+        Vec2::splat(32.0)
+    }
+
+    fn tileset(&self) -> Handle<Image> {
+        default()
+    }
+
+    fn atlas_grid(&self) -> (usize, usize) {
+        (8, 9)
+    }
+
+    fn base_tiles(&self) -> Vec<MapTile> {
+        self.tiles.iter().map(|&(x, y, tile)| MapTile { x, y, tile }).collect()
+    }
+
+    fn objects(&self) -> Vec<MapObject> {
+        self.objects.iter().map(|&(x, y, tile_id)| MapObject { x, y, object: GameObject { tile_id } }).collect()
+    }
+
+    fn tile_properties(&self, _tile_id: i32) -> Option<&Properties> {
+        None
+    }
+}
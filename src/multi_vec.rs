@@ -1,10 +1,59 @@
-#[derive(Debug, Clone)]
+use std::{collections::VecDeque, ops::{Index, IndexMut}};
+
+use bevy::math::IVec2;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// straight neighbours
+const OFFSETS_4: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+/// straight and diagonal neighbours
+const OFFSETS_8: [(isize, isize); 8] = [(1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+/// Cells addressed by signed coordinates, implemented by the bounded `MultiVec` and the unbounded `ChunkedGrid`
+pub trait Grid<T> {
+    /// None where no cell is stored
+    fn cell(&self, pos: IVec2) -> Option<&T>;
+    fn cell_mut(&mut self, pos: IVec2) -> Option<&mut T>;
+}
+
+/// Serialized as `{ "w": .., "h": .., "data": [..] }`, deserializing checks that `data` has `w * h` cells
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedMultiVec<T>")]
 pub struct MultiVec<T> {
     pub w: usize,
     pub h: usize,
     pub data: Vec<T>
 }
 
+#[derive(Debug, Display)]
+pub enum MultiVecError {
+    #[display(fmt = "{w}x{h} grid needs {} cells, got {len}", "w * h")]
+    WrongCellCount { w: usize, h: usize, len: usize },
+    #[display(fmt = "{w}x{h} grid has more cells than fit into memory")]
+    TooBig { w: usize, h: usize },
+}
+
+impl std::error::Error for MultiVecError {}
+
+#[derive(Deserialize)]
+struct UncheckedMultiVec<T> {
+    w: usize,
+    h: usize,
+    data: Vec<T>,
+}
+
+impl<T> TryFrom<UncheckedMultiVec<T>> for MultiVec<T> {
+    type Error = MultiVecError;
+
+    fn try_from(UncheckedMultiVec { w, h, data }: UncheckedMultiVec<T>) -> Result<Self, Self::Error> {
+        let cells = w.checked_mul(h).ok_or(MultiVecError::TooBig { w, h })?;
+        if data.len() != cells {
+            return Err(MultiVecError::WrongCellCount { w, h, len: data.len() });
+        }
+        Ok(MultiVec { w, h, data })
+    }
+}
+
 impl<T> Default for MultiVec<T> {
     fn default()->Self {
         MultiVec {
@@ -15,6 +64,15 @@ impl<T> Default for MultiVec<T> {
     }
 }
 
+/// Read-only rectangle of a `MultiVec`, coordinates are relative to the corner it starts at
+pub struct GridView<'a, T> {
+    grid: &'a MultiVec<T>,
+    x: usize,
+    y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
 pub struct RestVec<'a,T> {
     start: &'a mut [T],
     end: &'a mut [T],
@@ -77,6 +135,7 @@ impl<T> MultiVec<T> where T: Clone {
         self.data.iter()
     }
 
+    #[allow(dead_code)] // grid API, no caller yet
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
         self.data.iter_mut()
     }
@@ -97,6 +156,96 @@ impl<T> MultiVec<T> where T: Clone {
         })
     }
 
+    /// Neighbours sharing an edge with (x, y), only those inside the grid
+    pub fn neighbours4(&self, x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> {
+        Self::neighbours(x, y, self.w, self.h, &OFFSETS_4)
+    }
+
+    /// Neighbours sharing an edge or a corner with (x, y), only those inside the grid
+    #[allow(dead_code)] // grid API, no caller yet
+    pub fn neighbours8(&self, x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> {
+        Self::neighbours(x, y, self.w, self.h, &OFFSETS_8)
+    }
+
+    fn neighbours(x: usize, y: usize, w: usize, h: usize, offsets: &'static [(isize, isize)]) -> impl Iterator<Item = (usize, usize)> {
+        offsets.iter().filter_map(move |&(dx, dy)| {
+            let (nx, ny) = (x.checked_add_signed(dx)?, y.checked_add_signed(dy)?);
+            (nx < w && ny < h).then_some((nx, ny))
+        })
+    }
+
+    /// None if the rectangle doesn't fit into the grid
+    pub fn view(&self, x: usize, y: usize, w: usize, h: usize) -> Option<GridView<'_, T>> {
+        (x + w <= self.w && y + h <= self.h).then_some(GridView { grid: self, x, y, w, h })
+    }
+
+    pub fn row(&self, y: usize) -> Option<&[T]> {
+        (y < self.h).then(|| &self.data[y * self.w..(y + 1) * self.w])
+    }
+
+    #[allow(dead_code)] // grid API, no caller yet
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [T]> {
+        (y < self.h).then(|| &mut self.data[y * self.w..(y + 1) * self.w])
+    }
+
+    /// empty if x is outside of the grid
+    #[allow(dead_code)] // grid API, no caller yet
+    pub fn column(&self, x: usize) -> impl Iterator<Item = &T> {
+        let len = if x < self.w { self.h } else { 0 };
+        self.data.iter().skip(x).step_by(self.w.max(1)).take(len)
+    }
+
+    pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> MultiVec<U> {
+        MultiVec { w: self.w, h: self.h, data: self.data.iter().map(f).collect() }
+    }
+
+    /// panics if the grids differ in size
+    #[allow(dead_code)] // grid API, no caller yet
+    pub fn zip_with<U, V>(&self, other: &MultiVec<U>, mut f: impl FnMut(&T, &U) -> V) -> MultiVec<V> {
+        assert!(self.w == other.w && self.h == other.h, "grids should have the same size");
+        MultiVec { w: self.w, h: self.h, data: self.data.iter().zip(&other.data).map(|(a, b)| f(a, b)).collect() }
+    }
+
+    /// Cells 4-connected to (x, y) for which `include` holds, in breadth first order.
+    /// Empty if (x, y) is outside or not included itself.
+    pub fn flood_fill(&self, x: usize, y: usize, mut include: impl FnMut(&T) -> bool) -> Vec<(usize, usize)> {
+        let mut visited = MultiVec::new(false, self.w, self.h);
+        let mut filled = vec![];
+        if !self.get(x, y).is_some_and(&mut include) {
+            return filled;
+        }
+        visited[(x, y)] = true;
+        let mut queue = VecDeque::from([(x, y)]);
+        while let Some((x, y)) = queue.pop_front() {
+            filled.push((x, y));
+            for (nx, ny) in self.neighbours4(x, y) {
+                if !visited[(nx, ny)] && include(&self[(nx, ny)]) {
+                    visited[(nx, ny)] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        filled
+    }
+
+    /// Labels the 4-connected groups of cells for which `include` holds with 0, 1, ... in scan order,
+    /// None for cells that are not included. Also returns the number of groups.
+    pub fn connected_components(&self, include: impl FnMut(&T) -> bool) -> (MultiVec<Option<usize>>, usize) {
+        let included = self.map(include);
+        let mut labels = MultiVec::new(None, self.w, self.h);
+        let mut count = 0;
+        for (x, y, &cell) in included.enum_iter() {
+            if !cell || labels[(x, y)].is_some() {
+                continue;
+            }
+            for pos in included.flood_fill(x, y, |&cell| cell) {
+                labels[pos] = Some(count);
+            }
+            count += 1;
+        }
+        (labels, count)
+    }
+
     pub fn isolate<'a>(&'a mut self, x: usize, y: usize) -> Option<(&mut T, RestVec<'a, T>)> {
         self.get(x, y)?;
         let (start, rest) = self.data.split_at_mut(x + y * self.w);
@@ -115,6 +264,102 @@ impl<T> MultiVec<T> where T: Clone {
     }
 }
 
+impl<T: Clone + PartialEq> MultiVec<T> {
+    /// Run-length encoding in row order: (count, value) for each run of equal cells.
+    /// Maps are mostly big areas of the same tile, so this is a lot smaller than `data`.
+    pub fn to_runs(&self) -> Vec<(usize, T)> {
+        let mut runs: Vec<(usize, T)> = vec![];
+        for cell in &self.data {
+            match runs.last_mut() {
+                Some((count, value)) if value == cell => *count += 1,
+                _ => runs.push((1, cell.clone())),
+            }
+        }
+        runs
+    }
+
+    /// The counts are checked before anything is allocated, so a corrupt file can't ask for too much memory
+    pub fn from_runs(w: usize, h: usize, runs: &[(usize, T)]) -> Result<Self, MultiVecError> {
+        let cells = w.checked_mul(h).ok_or(MultiVecError::TooBig { w, h })?;
+        let len = runs.iter()
+            .try_fold(0usize, |len, (count, _)| len.checked_add(*count))
+            .unwrap_or(usize::MAX);
+        if len != cells {
+            return Err(MultiVecError::WrongCellCount { w, h, len });
+        }
+        let mut data = Vec::with_capacity(cells);
+        for (count, value) in runs {
+            data.extend(std::iter::repeat_n(value.clone(), *count));
+        }
+        Ok(MultiVec { w, h, data })
+    }
+}
+
+/// `#[serde(with = "multi_vec::runs")]` stores a `MultiVec` run-length encoded, see `MultiVec::to_runs`
+pub mod runs {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::MultiVec;
+
+    #[derive(Serialize, Deserialize)]
+    struct Runs<T> {
+        w: usize,
+        h: usize,
+        runs: Vec<(usize, T)>,
+    }
+
+    pub fn serialize<T, S>(grid: &MultiVec<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where T: Clone + PartialEq + Serialize, S: Serializer {
+        Runs { w: grid.w, h: grid.h, runs: grid.to_runs() }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<MultiVec<T>, D::Error>
+    where T: Clone + PartialEq + Deserialize<'de>, D: Deserializer<'de> {
+        let Runs { w, h, runs } = Runs::deserialize(deserializer)?;
+        MultiVec::from_runs(w, h, &runs).map_err(D::Error::custom)
+    }
+}
+
+impl<T: Clone> Grid<T> for MultiVec<T> {
+    fn cell(&self, pos: IVec2) -> Option<&T> {
+        self.get(usize::try_from(pos.x).ok()?, usize::try_from(pos.y).ok()?)
+    }
+
+    fn cell_mut(&mut self, pos: IVec2) -> Option<&mut T> {
+        self.get_mut(usize::try_from(pos.x).ok()?, usize::try_from(pos.y).ok()?)
+    }
+}
+
+impl<T> Index<(usize, usize)> for MultiVec<T> {
+    type Output = T;
+
+    /// panics outside of the grid
+    fn index(&self, (x, y): (usize, usize)) -> &T {
+        assert!(x < self.w && y < self.h, "({x},{y}) is outside of the {}x{} grid", self.w, self.h);
+        &self.data[x + y * self.w]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for MultiVec<T> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut T {
+        assert!(x < self.w && y < self.h, "({x},{y}) is outside of the {}x{} grid", self.w, self.h);
+        &mut self.data[x + y * self.w]
+    }
+}
+
+impl<'a, T> GridView<'a, T> {
+    pub fn get(&self, x: usize, y: usize) -> Option<&'a T> {
+        if x >= self.w || y >= self.h {
+            return None;
+        }
+        self.grid.data.get(self.x + x + (self.y + y) * self.grid.w)
+    }
+
+    pub fn enum_iter(&self) -> impl Iterator<Item = (usize, usize, &'a T)> + '_ {
+        (0..self.h).flat_map(move |y| (0..self.w).map(move |x| (x, y, self.get(x, y).expect("inside the view"))))
+    }
+}
+
 impl<'a, T>  RestVec<'a, T> {
     pub fn get(&self, x: usize, y: usize) -> Option<&T> {
         if x >= self.w || y >= self.h {
@@ -141,3 +386,136 @@ impl<'a, T>  RestVec<'a, T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Save {
+        #[serde(with = "runs")]
+        tiles: MultiVec<i32>,
+    }
+
+    fn grid(w: usize, h: usize, data: &[i32]) -> MultiVec<i32> {
+        MultiVec { w, h, data: data.to_vec() }
+    }
+
+    fn sorted(cells: impl Iterator<Item = (usize, usize)>) -> Vec<(usize, usize)> {
+        let mut cells = cells.collect::<Vec<_>>();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn neighbours_stay_inside_the_grid() {
+        let grid = MultiVec::new(0, 3, 2);
+        assert_eq!(sorted(grid.neighbours4(1, 0)), [(0, 0), (1, 1), (2, 0)]);
+        assert_eq!(sorted(grid.neighbours4(0, 0)), [(0, 1), (1, 0)]);
+        assert_eq!(sorted(grid.neighbours4(2, 1)), [(1, 1), (2, 0)]);
+        assert_eq!(sorted(MultiVec::new(0, 1, 1).neighbours4(0, 0)), []);
+        assert_eq!(sorted(MultiVec::new(0, 3, 3).neighbours4(1, 1)), [(0, 1), (1, 0), (1, 2), (2, 1)]);
+
+        assert_eq!(sorted(grid.neighbours8(1, 0)), [(0, 0), (0, 1), (1, 1), (2, 0), (2, 1)]);
+        assert_eq!(sorted(grid.neighbours8(0, 0)), [(0, 1), (1, 0), (1, 1)]);
+        assert_eq!(sorted(MultiVec::new(0, 1, 1).neighbours8(0, 0)), []);
+        assert_eq!(MultiVec::new(0, 3, 3).neighbours8(1, 1).count(), 8);
+    }
+
+    #[test]
+    fn rows_views_and_edges() {
+        let grid = grid(3, 2, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(grid.row(0), Some(&[1, 2, 3][..]));
+        assert_eq!(grid.row(1), Some(&[4, 5, 6][..]));
+        assert_eq!(grid.row(2), None);
+        assert_eq!(grid.column(0).collect::<Vec<_>>(), [&1, &4]);
+        assert_eq!(grid.column(2).collect::<Vec<_>>(), [&3, &6]);
+        assert_eq!(grid.column(3).count(), 0);
+        assert_eq!(MultiVec::new(0, 0, 0).column(0).count(), 0);
+        assert_eq!(grid.get(3, 0), None);
+        assert_eq!(grid.cell(IVec2::new(-1, 0)), None);
+        assert_eq!(grid.cell(IVec2::new(2, 1)), Some(&6));
+        assert_eq!(grid.enum_iter().last(), Some((2, 1, &6)));
+
+        let view = grid.view(1, 0, 2, 2).unwrap();
+        assert_eq!(view.enum_iter().map(|(_, _, &cell)| cell).collect::<Vec<_>>(), [2, 3, 5, 6]);
+        assert_eq!(view.get(2, 0), None);
+        assert!(grid.view(2, 0, 2, 1).is_none());
+    }
+
+    #[test]
+    fn mutation_map_and_zip() {
+        let mut grid = grid(3, 2, &[1, 2, 3, 4, 5, 6]);
+        grid.row_mut(1).unwrap().reverse();
+        assert!(grid.row_mut(2).is_none());
+        grid.iter_mut().for_each(|cell| *cell *= 10);
+        assert_eq!(grid.data, [10, 20, 30, 60, 50, 40]);
+
+        let halves = grid.map(|&cell| cell / 2);
+        assert_eq!((halves.w, halves.h), (3, 2));
+        assert_eq!(halves.data, [5, 10, 15, 30, 25, 20]);
+        let sums = grid.zip_with(&halves, |&a, &b| a + b);
+        assert_eq!(sums.data, [15, 30, 45, 90, 75, 60]);
+    }
+
+    #[test]
+    #[should_panic(expected = "same size")]
+    fn zip_with_needs_equal_sizes() {
+        MultiVec::new(0, 2, 2).zip_with(&MultiVec::new(0, 2, 3), |a, b| a + b);
+    }
+
+    #[test]
+    fn regions() {
+        let grid = grid(4, 3, &[
+            1, 1, 0, 1,
+            0, 1, 0, 1,
+            1, 0, 0, 1,
+        ]);
+        assert_eq!(sorted(grid.flood_fill(0, 0, |&cell| cell == 1).into_iter()), [(0, 0), (1, 0), (1, 1)]);
+        assert!(grid.flood_fill(2, 0, |&cell| cell == 1).is_empty());
+        assert!(grid.flood_fill(4, 0, |&cell| cell == 1).is_empty());
+        let (labels, count) = grid.connected_components(|&cell| cell == 1);
+        assert_eq!(count, 3);
+        assert_eq!(labels.data, [
+            Some(0), Some(0), None, Some(1),
+            None, Some(0), None, Some(1),
+            Some(2), None, None, Some(1),
+        ]);
+    }
+
+    #[test]
+    fn runs_round_trip() {
+        let grid = grid(4, 3, &[1, 1, 1, 2, 2, 2, 2, 2, 3, 1, 1, 1]);
+        let runs = grid.to_runs();
+        assert_eq!(runs, [(3, 1), (5, 2), (1, 3), (3, 1)]);
+        let decoded = MultiVec::from_runs(4, 3, &runs).unwrap();
+        assert_eq!((decoded.w, decoded.h, decoded.data), (4, 3, grid.data));
+
+        assert!(MultiVec::<i32>::default().to_runs().is_empty());
+        assert!(MultiVec::<i32>::from_runs(0, 0, &[]).unwrap().data.is_empty());
+    }
+
+    #[test]
+    fn runs_serde_round_trip() {
+        let save = Save { tiles: grid(2, 2, &[7, 7, 7, 8]) };
+        let json = serde_json::to_string(&save).unwrap();
+        assert_eq!(json, r#"{"tiles":{"w":2,"h":2,"runs":[[3,7],[1,8]]}}"#);
+        let loaded: Save = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.tiles.data, save.tiles.data);
+    }
+
+    #[test]
+    fn corrupt_runs_are_rejected() {
+        assert!(matches!(MultiVec::from_runs(2, 2, &[(3, 0)]), Err(MultiVecError::WrongCellCount { len: 3, .. })));
+        assert!(matches!(MultiVec::from_runs(2, 2, &[(3, 0), (2, 1)]), Err(MultiVecError::WrongCellCount { len: 5, .. })));
+        // would need far too much memory if the counts were trusted
+        assert!(matches!(MultiVec::from_runs(2, 2, &[(usize::MAX, 0)]), Err(MultiVecError::WrongCellCount { .. })));
+        assert!(matches!(MultiVec::from_runs(2, 2, &[(usize::MAX, 0), (2, 0)]), Err(MultiVecError::WrongCellCount { .. })));
+        assert!(matches!(MultiVec::from_runs(usize::MAX, 2, &[(1, 0)]), Err(MultiVecError::TooBig { .. })));
+
+        assert!(serde_json::from_str::<Save>(r#"{"tiles":{"w":2,"h":2,"runs":[[5,0]]}}"#).is_err());
+        assert!(serde_json::from_str::<Save>(r#"{"tiles":{"w":4294967296,"h":4294967296,"runs":[[1,0]]}}"#).is_err());
+        assert!(serde_json::from_str::<MultiVec<i32>>(r#"{"w":2,"h":2,"data":[1,2,3]}"#).is_err());
+        assert!(serde_json::from_str::<MultiVec<i32>>(r#"{"w":4294967296,"h":4294967296,"data":[]}"#).is_err());
+    }
+}
@@ -1,18 +1,21 @@
-use bevy::input::ButtonState;
-use bevy::input::keyboard::KeyboardInput;
 use bevy::prelude::*;
 use bevy::sprite::collide_aabb;
 
-use crate::player::Player;
-use crate::progress::{self, DestroyProgress};
+use crate::app_state::AppState;
+use crate::player::{Player, PLAYER_SIZE};
+use crate::progress::{self, BuildProgress, DestroyProgress};
 use crate::game_object::{GameObject, ObjectType};
+use crate::game_tile::MapData;
+use crate::items::{Inventory, ItemRegistry};
+use crate::replay::{GameClock, GameInput, TickSet};
+use crate::tile_pos::WorldPos;
 
 pub struct ObjectInteractionPlugin;
 
 impl Plugin for ObjectInteractionPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, startup);
-        app.add_systems(Update, update);
+        app.add_systems(FixedUpdate, update.in_set(TickSet::Gameplay).run_if(in_state(AppState::Playing)));
         app.add_systems(Update, update_inventory_text);
     }
 }
@@ -64,19 +67,24 @@ fn update(
     mut players: Query<(&mut Player, &Transform)>,
     objects: Query<(Entity, &GameObject, &Transform)>,
     mut texts: Query<(&mut InteractText, &mut Text, &mut Visibility)>,
-    mut key_evr: EventReader<KeyboardInput>,
-    time: Res<Time>,
+    input: Res<GameInput>,
+    clock: Res<GameClock>,
     progress_stuff: Res<progress::ProgressStuff>,
     running_progress: Query<&DestroyProgress>,
+    map_data: Res<MapData>,
+    item_registry: Res<ItemRegistry>,
 ) {
     let (_, player_transform) = players.iter_mut().next().expect("no player found");
     let progress_running = running_progress.iter().next().is_some();
 
-    let object = objects.iter().find(|(_, _, tile_transform)| collide_aabb::collide(
+    // objects are one tile big, only those on cells next to the player can touch it
+    let object = map_data.objects_in_radius(WorldPos::from(player_transform.translation), 1.5).into_iter()
+        .filter_map(|entity| objects.get(entity).ok())
+        .find(|(_, _, tile_transform)| collide_aabb::collide(
             tile_transform.translation,
             Vec2::new(1.0, 1.0),
             player_transform.translation,
-            player_transform.scale.truncate() * 32.0
+            Vec2::splat(PLAYER_SIZE)
     ).is_some());
 
     let mut text = texts.iter_mut().next().expect("no text found");
@@ -101,35 +109,47 @@ fn update(
     // handle key press: start destroy progress
     if let Some((entity, object, transform)) = object  {
         if !progress_running { Some(()) } else { None }
-            .and(if key_evr.read().any(|ev| ev.state == ButtonState::Pressed && ev.key_code == Some(KeyCode::E)) { Some(()) } else { None })
+            .and(if input.just_pressed(KeyCode::E) { Some(()) } else { None })
             .and(Some(DestroyProgress {
                 target: entity,
                 others: vec![],
                 get_inv: default(),
-                start_time: time.elapsed_seconds(),
+                start_time: clock.seconds(),
                 time_to_destroy: 2.0,
             }))
             .and_then(|mut progress| {
-                match object.get_type() {
-                    Some(ObjectType::Tree) => progress.get_inv.wood += 10,
-                    Some(ObjectType::Ship) => progress.get_inv.weapons += 10,
-                    Some(ObjectType::Stone) => progress.get_inv.stone += 10,
-                    Some(ObjectType::Campfire) => return None,
-                    None => return None,
-                }
+                progress.get_inv = item_registry.drops(object.get_type()?)?.clone();
                 Some(progress)
             })
             .map(|progress| {
-                progress::start_destroy_progress(progress, &mut commands, progress_stuff, transform.translation.truncate())
+                progress::start_destroy_progress(progress, &mut commands, &progress_stuff, transform.translation.truncate())
             });
     }
 }
 
 fn update_inventory_text(
     mut inv_text: Query<(&mut InventoryText, &mut Text)>,
-    players: Query<&Player>) {
+    players: Query<&Player>,
+    builds: Query<&BuildProgress>,
+    item_registry: Res<ItemRegistry>) {
 
     let player = players.iter().next().expect("no player found");
     let mut inventory_text = inv_text.iter_mut().next().expect("no inventory text found");
-    inventory_text.1.sections[0].value = format!("Wood: {}\nStone: {}\nWeapons: {}", player.inventory.wood, player.inventory.stone, player.inventory.weapons);
+    let mut reserved = Inventory::default();
+    for build in &builds {
+        reserved += &build.price_inv;
+    }
+    let mut shown = player.inventory.clone();
+    shown += &reserved; // items that are all reserved still get a line
+    inventory_text.1.sections[0].value = if shown.is_empty() {
+        "[empty inventory]".to_string()
+    } else {
+        shown.iter()
+            .map(|(id, _)| match reserved.count(id) {
+                0 => format!("{}: {}", item_registry.name(id), player.inventory.count(id)),
+                reserved => format!("{}: {} ({} reserved)", item_registry.name(id), player.inventory.count(id), reserved),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
 }
@@ -0,0 +1,312 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::prelude::*;
+
+use crate::{
+    game_object::GameObject,
+    game_tile::MapData,
+    multi_vec::MultiVec,
+    terrain_cost::{self, Quarter},
+    tile_metadata::TileMetadata,
+    tile_pos::WorldPos,
+};
+
+/// 8-neighbourhood, straight steps first
+const NEIGHBOURS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+/// Movement costs of the whole map at quarter tile resolution, the same grid the collision uses for terrain.
+/// None for quarters that can't be entered. Build it again when the map or the obstacles change.
+#[allow(dead_code)] // API for click-to-move, NPCs and animals, which don't exist yet
+pub struct NavGrid {
+    costs: MultiVec<Option<f32>>,
+}
+
+/// Dijkstra distances to a goal, lets any number of agents walk there by following the slope
+#[allow(dead_code)] // API for NPCs and animals, which don't exist yet
+pub struct FlowField {
+    goal: WorldPos,
+    distances: MultiVec<f32>,
+}
+
+/// entry of the open list, the smallest cost comes out of the `BinaryHeap` first
+struct Open {
+    cost: f32,
+    index: usize,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+#[allow(dead_code)] // API for click-to-move, NPCs and animals, which don't exist yet
+impl NavGrid {
+    pub fn new(map_data: &MapData, metadata: &TileMetadata) -> Self {
+        let tiles = map_data.tiles();
+        let mut costs = MultiVec::new(None, tiles.w * 2, tiles.h * 2);
+        for (x, y, cost) in costs.enum_iter_mut() {
+            *cost = terrain_cost::quarter_cost(map_data, metadata, (x as i32, y as i32));
+        }
+        NavGrid { costs }
+    }
+
+    /// Blocks the hitboxes of objects, e.g. trees, stones and buildings
+    pub fn with_objects<'a>(mut self, objects: impl IntoIterator<Item = (&'a GameObject, &'a Transform)>) -> Self {
+        for (object, transform) in objects {
+            if let Some(size) = object.hitbox() {
+                self.block(Rect::from_center_size(transform.translation.truncate(), size));
+            }
+        }
+        self
+    }
+
+    /// Dynamic obstacle: every quarter overlapping `area` can't be entered anymore
+    pub fn block(&mut self, area: Rect) {
+        let (min_x, min_y) = terrain_cost::quarter_at(WorldPos(area.min));
+        let (max_x, max_y) = terrain_cost::quarter_at(WorldPos(area.max));
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                if let Some(cost) = self.index((x, y)).map(|index| &mut self.costs.data[index]) {
+                    *cost = None;
+                }
+            }
+        }
+    }
+
+    pub fn cost(&self, quarter: Quarter) -> Option<f32> {
+        self.costs.data[self.index(quarter)?]
+    }
+
+    fn index(&self, (x, y): Quarter) -> Option<usize> {
+        self.costs.xy_to_index(usize::try_from(x).ok()?, usize::try_from(y).ok()?)
+    }
+
+    fn quarter(&self, index: usize) -> Quarter {
+        let (x, y) = self.costs.index_to_xy(index).expect("index should be inside the grid");
+        (x as i32, y as i32)
+    }
+
+    /// Steps to the neighbours of `quarter` with their costs. Diagonal steps need both straight neighbours
+    /// to be free, so paths don't cut through the corner of a wall.
+    fn steps(&self, quarter: Quarter) -> impl Iterator<Item = (Quarter, f32)> + '_ {
+        NEIGHBOURS.into_iter().filter_map(move |(dx, dy)| {
+            let to = (quarter.0 + dx, quarter.1 + dy);
+            if dx != 0 && dy != 0 && (self.cost((quarter.0 + dx, quarter.1)).is_none() || self.cost((quarter.0, quarter.1 + dy)).is_none()) {
+                return None;
+            }
+            Some((to, terrain_cost::step_cost(quarter, to, |quarter| self.cost(quarter))?))
+        })
+    }
+
+    /// A* from `start` to `goal`. Returns the waypoints after `start`, quarter centres ending with `goal` itself.
+    /// None if the goal can't be reached.
+    pub fn find_path(&self, start: WorldPos, goal: WorldPos) -> Option<Vec<WorldPos>> {
+        let start_quarter = terrain_cost::quarter_at(start);
+        let goal_quarter = terrain_cost::quarter_at(goal);
+        let start_index = self.index(start_quarter)?;
+        let goal_index = self.index(goal_quarter)?;
+        self.cost(goal_quarter)?;
+        if start_index == goal_index {
+            return Some(vec![goal]);
+        }
+
+        // the cheapest terrain keeps the estimate admissible
+        let min_cost = self.costs.iter().flatten().copied().fold(f32::INFINITY, f32::min);
+        let estimate = |quarter: Quarter| {
+            let (dx, dy) = ((quarter.0 - goal_quarter.0).abs() as f32, (quarter.1 - goal_quarter.1).abs() as f32);
+            // octile distance in quarters, half a tile each
+            (dx.max(dy) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dy)) * 0.5 * min_cost
+        };
+
+        let mut distances = MultiVec::new_like(f32::INFINITY, &self.costs);
+        let mut came_from = MultiVec::new_like(None, &self.costs);
+        let mut open = BinaryHeap::from([Open { cost: estimate(start_quarter), index: start_index }]);
+        distances.data[start_index] = 0.0;
+        while let Some(Open { cost, index }) = open.pop() {
+            if index == goal_index {
+                break;
+            }
+            let quarter = self.quarter(index);
+            let distance = distances.data[index];
+            if cost > distance + estimate(quarter) {
+                continue; // already expanded with a shorter distance
+            }
+            for (to, step) in self.steps(quarter) {
+                let to_index = self.index(to).expect("steps only lead into the grid");
+                if distance + step < distances.data[to_index] {
+                    distances.data[to_index] = distance + step;
+                    came_from.data[to_index] = Some(index);
+                    open.push(Open { cost: distance + step + estimate(to), index: to_index });
+                }
+            }
+        }
+
+        let mut path = vec![goal];
+        let mut index = came_from.data[goal_index]?;
+        while index != start_index {
+            path.push(terrain_cost::quarter_centre(self.quarter(index)));
+            index = came_from.data[index].expect("every reached quarter but the start has a predecessor");
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Distances from every quarter to `goal`
+    pub fn flow_field(&self, goal: WorldPos) -> FlowField {
+        let mut distances = MultiVec::new_like(f32::INFINITY, &self.costs);
+        let mut open = BinaryHeap::new();
+        let goal_quarter = terrain_cost::quarter_at(goal);
+        if let Some(goal_index) = self.index(goal_quarter).filter(|_| self.cost(goal_quarter).is_some()) {
+            distances.data[goal_index] = 0.0;
+            open.push(Open { cost: 0.0, index: goal_index });
+        }
+        while let Some(Open { cost, index }) = open.pop() {
+            if cost > distances.data[index] {
+                continue;
+            }
+            // steps cost the same both ways, so walking away from the goal gives the distances towards it
+            for (to, step) in self.steps(self.quarter(index)) {
+                let to_index = self.index(to).expect("steps only lead into the grid");
+                if cost + step < distances.data[to_index] {
+                    distances.data[to_index] = cost + step;
+                    open.push(Open { cost: cost + step, index: to_index });
+                }
+            }
+        }
+        FlowField { goal, distances }
+    }
+}
+
+#[allow(dead_code)] // API for NPCs and animals, which don't exist yet
+impl FlowField {
+    /// Where to walk from `pos`: towards the neighbouring quarter closest to the goal, straight at the goal once
+    /// in its quarter. None if the goal can't be reached from here or `pos` is already on it.
+    pub fn direction(&self, pos: WorldPos) -> Option<Vec2> {
+        let quarter = terrain_cost::quarter_at(pos);
+        let distance = *self.distance(quarter)?;
+        if !distance.is_finite() {
+            return None;
+        }
+        if distance == 0.0 {
+            return (self.goal.0 - pos.0).try_normalize();
+        }
+        let next = NEIGHBOURS.into_iter()
+            .map(|(dx, dy)| (quarter.0 + dx, quarter.1 + dy))
+            .filter_map(|to| Some((to, *self.distance(to)?)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .filter(|(_, next_distance)| *next_distance < distance)?;
+        (terrain_cost::quarter_centre(next.0).0 - pos.0).try_normalize()
+    }
+
+    fn distance(&self, (x, y): Quarter) -> Option<&f32> {
+        self.distances.get(usize::try_from(x).ok()?, usize::try_from(y).ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One char per quarter, the first row is the top: `.` walkable, `~` twice as slow, `#` blocked
+    fn nav_grid(rows: &[&str]) -> NavGrid {
+        let (w, h) = (rows[0].len(), rows.len());
+        let mut costs = MultiVec::new(None, w, h);
+        for (row, line) in rows.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                costs[(x, h - 1 - row)] = match c {
+                    '.' => Some(1.0),
+                    '~' => Some(2.0),
+                    _ => None,
+                };
+            }
+        }
+        NavGrid { costs }
+    }
+
+    fn at(x: i32, y: i32) -> WorldPos {
+        terrain_cost::quarter_centre((x, y))
+    }
+
+    fn quarters(path: &[WorldPos]) -> Vec<Quarter> {
+        path.iter().map(|&pos| terrain_cost::quarter_at(pos)).collect()
+    }
+
+    #[test]
+    fn path_goes_around_a_wall() {
+        let grid = nav_grid(&[
+            ".#..",
+            ".#..",
+            "....",
+        ]);
+        let path = grid.find_path(at(0, 2), at(2, 2)).expect("goal should be reachable");
+        // no diagonals past the ends of the wall
+        assert_eq!(quarters(&path), [(0, 1), (0, 0), (1, 0), (2, 0), (2, 1), (2, 2)]);
+        assert_eq!(path.last(), Some(&at(2, 2)));
+
+        let flow_field = grid.flow_field(at(2, 2));
+        let direction = flow_field.direction(at(0, 2)).expect("goal should be reachable");
+        assert!(direction.y < -0.9, "should head down to the gap, not {direction}");
+        assert_eq!(flow_field.direction(at(2, 2)), None);
+    }
+
+    #[test]
+    fn path_avoids_slow_terrain_when_cheaper() {
+        let grid = nav_grid(&[
+            "....",
+            ".~~.",
+        ]);
+        let path = grid.find_path(at(0, 0), at(3, 0)).unwrap();
+        assert_eq!(quarters(&path), [(1, 1), (2, 1), (3, 0)]);
+    }
+
+    #[test]
+    fn blocked_goal_is_unreachable() {
+        let grid = nav_grid(&[
+            "...#.",
+            "..#..",
+            "...#.",
+        ]);
+        assert!(grid.find_path(at(0, 0), at(2, 1)).is_none(), "goal on a wall");
+        assert!(grid.find_path(at(0, 0), at(4, 1)).is_none(), "goal behind walls");
+        assert!(grid.find_path(at(0, 0), at(9, 9)).is_none(), "goal outside the grid");
+        assert_eq!(grid.flow_field(at(4, 1)).direction(at(0, 0)), None);
+        assert!(grid.find_path(at(0, 0), at(1, 2)).is_some());
+    }
+
+    #[test]
+    fn diagonals_dont_cut_corners() {
+        let open = nav_grid(&[
+            "..",
+            "..",
+        ]);
+        assert_eq!(quarters(&open.find_path(at(0, 0), at(1, 1)).unwrap()), [(1, 1)]);
+
+        let one_wall = nav_grid(&[
+            "..",
+            ".#",
+        ]);
+        assert_eq!(quarters(&one_wall.find_path(at(0, 0), at(1, 1)).unwrap()), [(0, 1), (1, 1)]);
+
+        let two_walls = nav_grid(&[
+            "#.",
+            ".#",
+        ]);
+        assert!(two_walls.find_path(at(0, 0), at(1, 1)).is_none());
+        assert_eq!(two_walls.flow_field(at(1, 1)).direction(at(0, 0)), None);
+    }
+}
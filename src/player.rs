@@ -1,13 +1,13 @@
-use std::{fmt::Formatter, fmt::Display};
-use derive_more::{Add, Sub, AddAssign, SubAssign};
-use std::ops::Not;
 use bevy::prelude::*;
 use crate::{
-    game_tile::{
-        MapData,
-        GameTile,
-    },
-    tile_world::check_collision,
+    app_state::AppState,
+    collision::{Collider, Collisions},
+    game_tile::MapData,
+    items::Inventory,
+    replay::{GameInput, TickSet},
+    terrain_cost,
+    tile_metadata::TileMetadata,
+    tile_pos::WorldPos,
 };
 
 pub struct PlayerPlugin;
@@ -15,11 +15,17 @@ pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
   fn build(&self,  app: &mut App) {
     app.add_systems(Startup, setup)
-       .add_systems(Update, animate_sprite)
-       .add_systems(Update, keyboard_events);
+       .add_systems(Update, animate_sprite.run_if(in_state(AppState::Playing)))
+       .add_systems(FixedUpdate, keyboard_events.in_set(TickSet::Gameplay).run_if(in_state(AppState::Playing)));
   }
 }
 
+/// Size of the player's hitbox in tiles
+pub const PLAYER_SIZE: f32 = 0.6;
+
+/// Size of one frame in the sprite sheet, drawn `PLAYER_SIZE` tiles big in the world
+const PLAYER_FRAME_SIZE: Vec2 = Vec2::new(24.0, 24.0);
+
 #[derive(Component)]
 pub struct Player {
     pub inventory: Inventory,
@@ -28,41 +34,17 @@ pub struct Player {
 }
 
 impl Player {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         #[cfg(feature = "cheat")]
-        return Player { inventory: Inventory { wood: 0, stone: 0, weapons: 0 }, ghost: false, };
+        return Player { inventory: Inventory::default(), ghost: false, };
         #[cfg(not(feature = "cheat"))]
-        return Player { inventory: Inventory { wood: 0, stone: 0, weapons: 0 },};
+        return Player { inventory: Inventory::default(),};
     }
-    fn check_collision(
-        &self, 
-        map_data: &MapData,
-        tiles: &Query<&GameTile>,
-        player_transform: &Transform,
-    ) -> bool {
+    fn is_ghost(&self) -> bool {
         #[cfg(feature = "cheat")]
-        return self.ghost.not() && check_collision(&map_data, &tiles, &player_transform);
+        return self.ghost;
         #[cfg(not(feature = "cheat"))]
-        return check_collision(&map_data, &tiles, &player_transform);
-    }
-}
-
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Add, Sub, AddAssign, SubAssign)]
-pub struct Inventory {
-    pub wood: i32,
-    pub stone: i32,
-    pub weapons: i32,
-}
-impl Display for Inventory {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        vec![if self.wood > 0 { Some(format!("{} wood", self.wood)) } else { None },
-             if self.stone > 0 { Some(format!("{} stone", self.stone)) } else { None },
-             if self.weapons > 0 { Some(format!("{} weapons", self.weapons)) } else { None }]
-            .into_iter()
-            .filter_map(|x| x)
-            .collect::<Vec<String>>()
-            .join(", ")
-            .fmt(f)
+        return false;
     }
 }
 
@@ -116,12 +98,12 @@ fn setup(
 ) {
     let texture_handle = asset_server.load("textures/gabe-idle-run.png");
     let texture_atlas =
-        TextureAtlas::from_grid(texture_handle, Vec2::new(24.0, 24.0), 7, 1, None, None);
+        TextureAtlas::from_grid(texture_handle, PLAYER_FRAME_SIZE, 7, 1, None, None);
     let texture_atlas_handle = texture_atlases.add(texture_atlas);
     // Use only the subset of sprites in the sheet that make up the run animation
     let animation_indices = AnimationIndices { first: 1, last: 6, mirrored: false, walking: false };
 
-    let player_size = 0.6 / 32.0;
+    let player_size = PLAYER_SIZE / PLAYER_FRAME_SIZE.x; // sprite scale, so sprite and hitbox match
     let camera_scale = 0.007;
 
     let camera = commands.spawn(
@@ -139,6 +121,7 @@ fn setup(
         animation_indices,
         AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
         Player::new(),
+        Collider { size: Vec2::splat(PLAYER_SIZE) },
         Name::new("Player"),
         
     )).id();
@@ -149,53 +132,42 @@ fn setup(
 
 fn keyboard_events(
     // mut key_evr: EventReader<KeyboardInput>,
-    input: Res<Input<KeyCode>>,
-    time: Res<Time>,
+    input: Res<GameInput>,
+    time: Res<Time>, // the fixed step in `FixedUpdate`
+    collisions: Collisions,
     map_data: Res<MapData>,
-    tiles: Query<&GameTile>,
-    mut players: Query<(&mut Player, &mut AnimationIndices, &mut Transform)>,
+    tile_metadata: Res<TileMetadata>,
+    mut players: Query<(&mut Player, &mut AnimationIndices, &mut Transform, &Collider)>,
 ) {
-    let speed: f32 = 1.0;
-    
-    let (mut player, mut indices, mut player_transform) = players.iter_mut().next().expect("No player found");
-    let mut transform = *player_transform;
-    indices.walking = false;
+    let (mut player, mut indices, mut player_transform, collider) = players.iter_mut().next().expect("No player found");
+    // slower on expensive terrain, sampled where the feet are
+    let feet = player_transform.translation.truncate() - Vec2::new(0.0, collider.size.y / 2.0);
+    let speed = 1.0 / terrain_cost::cost_at(WorldPos(feet), &map_data, &tile_metadata);
+    let mut direction = Vec2::ZERO;
     if input.pressed(KeyCode::W) {
-        transform.translation.y += speed * time.delta_seconds();
-        if player.check_collision(&map_data, &tiles, &transform){
-            transform = *player_transform;
-        } else {
-            *player_transform = transform;
-            indices.walking = true;
-        }
+        direction.y += 1.0;
     }
     if input.pressed(KeyCode::S) {
-        transform.translation.y -= speed * time.delta_seconds();
-        if player.check_collision(&map_data, &tiles, &transform){
-            transform = *player_transform;
-        } else {
-            *player_transform = transform;
-            indices.walking = true;
-        }
+        direction.y -= 1.0;
     }
     if input.pressed(KeyCode::A) {
-        transform.translation.x -= speed * time.delta_seconds();
+        direction.x -= 1.0;
         indices.mirrored = true;
-        if player.check_collision(&map_data, &tiles, &transform){
-            transform = *player_transform;
-        } else {
-            *player_transform = transform;
-            indices.walking = true;
-        }
     }
     if input.pressed(KeyCode::D) {
-        transform.translation.x += speed * time.delta_seconds();
+        direction.x += 1.0;
         indices.mirrored = false;
-        if player.check_collision(&map_data, &tiles, &transform){
-        } else {
-            *player_transform = transform;
-            indices.walking = true;
-        }
+    }
+    let delta = direction * speed * time.delta_seconds();
+    let pos = player_transform.translation.truncate();
+    let new_pos = if player.is_ghost() {
+        pos + delta
+    } else {
+        collisions.move_and_slide(pos, collider.size, delta)
+    };
+    indices.walking = new_pos != pos;
+    if indices.walking {
+        player_transform.translation = new_pos.extend(player_transform.translation.z);
     }
     #[cfg(feature = "cheat")]
     if input.pressed(KeyCode::X) {
@@ -0,0 +1,49 @@
+use std::collections::HashSet;
+
+use crate::{
+    game_tile::{Corner, MapData},
+    multi_vec::MultiVec,
+    tile_metadata::TileMetadata,
+    tile_pos::TilePos,
+};
+
+/// Where the player starts on a fully generated map: a walkable cell in the largest connected land region.
+/// Cells with one of the `preferred_tiles` win, then cells without objects next to them, then cells near the centre.
+/// None if the map has no walkable cell at all.
+pub fn choose_spawn(
+    map_data: &MapData,
+    metadata: &TileMetadata,
+    objects: &HashSet<TilePos>,
+    preferred_tiles: &[i32],
+) -> Option<(usize, usize)> {
+    let tiles = map_data.tiles();
+    let walkable = tiles.map(|tile| tile.is_some_and(|tile| Corner::CLOCKWISE.into_iter()
+        .all(|corner| metadata.can_enter(&tile, corner).unwrap_or(true))));
+    let region = largest_region(&walkable);
+
+    let centre = ((tiles.w / 2) as i64, (tiles.h / 2) as i64);
+    let near_object = |x: usize, y: usize| (-1..=1)
+        .any(|dx| (-1..=1).any(|dy| objects.contains(&TilePos::from_index(x, y).offset(dx, dy))));
+    region.into_iter()
+        .filter(|&(x, y)| !objects.contains(&TilePos::from_index(x, y)))
+        .min_by_key(|&(x, y)| {
+            let preferred = map_data.get(x, y).is_some_and(|tile| preferred_tiles.contains(&tile.tile_id));
+            let distance_to_centre = (x as i64 - centre.0).pow(2) + (y as i64 - centre.1).pow(2);
+            (!preferred, near_object(x, y), distance_to_centre, y, x)
+        })
+}
+
+/// cells of the biggest 4-connected group of `true` cells
+fn largest_region(cells: &MultiVec<bool>) -> Vec<(usize, usize)> {
+    let (labels, count) = cells.connected_components(|&cell| cell);
+    let mut sizes = vec![0; count];
+    for label in labels.iter().flatten() {
+        sizes[*label] += 1;
+    }
+    // the first of equally big regions, like the scan order before
+    let Some(largest) = (0..count).rev().max_by_key(|&label| sizes[label]) else { return vec![] };
+    labels.enum_iter()
+        .filter(|(_, _, label)| **label == Some(largest))
+        .map(|(x, y, _)| (x, y))
+        .collect()
+}
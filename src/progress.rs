@@ -1,10 +1,13 @@
 use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
 
 use crate::{
-    player::{Player, Inventory}, 
+    app_state::AppState,
+    items::{ChangeReason, Inventory, PlayerInventory},
     crafting::Buildable, 
-    tile_world::{create_bundle_for_tile, TileAssets},
+    tile_world::TileSprites,
+    tile_pos::{TilePos, WorldPos},
     game_object::{ObjectType, GameObject},
+    replay::{GameClock, GameInput, TickSet},
 };
 
 #[derive(Component)]
@@ -19,12 +22,23 @@ pub struct DestroyProgress {
 #[derive(Component)]
 pub struct BuildProgress {
     pub others: Vec<Entity>,
-    pub price_inv: Inventory,
+    pub price_inv: Inventory, // taken from the player when the build started, refunded if it is cancelled
     pub start_time: f32,
     pub time_to_build: f32,
     pub buildable: Buildable,
 }
 
+/// Sent when a building is done, after it was spawned
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BuildFinished {
+    pub buildable: Buildable,
+    pub pos: TilePos,
+}
+
+/// Marks both parts of a progress bar, so they can be removed together with the world
+#[derive(Component)]
+pub struct ProgressBar;
+
 pub struct ProgressPlugin;
 
 #[derive(Resource)]
@@ -36,9 +50,9 @@ pub struct ProgressStuff {
 
 impl Plugin for ProgressPlugin {
     fn build(&self, app: &mut App) {
+        app.add_event::<BuildFinished>();
         app.add_systems(Startup, startup);
-        app.add_systems(Update, update_destroy);
-        app.add_systems(Update, update_build);
+        app.add_systems(FixedUpdate, (update_destroy, update_build).in_set(TickSet::Gameplay).run_if(in_state(AppState::Playing)));
     }
 }
 
@@ -57,15 +71,15 @@ fn startup(
 pub fn start_destroy_progress(
     mut progress: DestroyProgress,
     commands: &mut Commands,
-    progress_stuff: Res<ProgressStuff>,
+    progress_stuff: &ProgressStuff,
     pos: Vec2,
 ) {
-    let bg = commands.spawn(MaterialMesh2dBundle {
+    let bg = commands.spawn((MaterialMesh2dBundle {
         material: progress_stuff.bg_material.clone(),
         mesh: progress_stuff.mesh.clone().into(),
         transform: Transform::from_translation(Vec3::new(pos.x, pos.y, 9.0)).with_scale(Vec3::new(0.55, 0.1, 1.0)),
         ..Default::default()
-    });
+    }, ProgressBar));
     progress.others.push(bg.id());
     commands.spawn((
         MaterialMesh2dBundle {
@@ -73,22 +87,22 @@ pub fn start_destroy_progress(
             mesh: progress_stuff.mesh.clone().into(),
             transform: Transform::from_translation(Vec3::new(pos.x, pos.y, 10.0)).with_scale(Vec3::new(0.0, 0.03, 1.0)),
             ..Default::default()
-        }, progress
+        }, progress, ProgressBar
     ));
 }
 
 pub fn start_build_progress(
     mut progress: BuildProgress,
     commands: &mut Commands,
-    progress_stuff: Res<ProgressStuff>,
+    progress_stuff: &ProgressStuff,
     pos: Vec2,
 ) {
-    let bg = commands.spawn(MaterialMesh2dBundle {
+    let bg = commands.spawn((MaterialMesh2dBundle {
         material: progress_stuff.bg_material.clone(),
         mesh: progress_stuff.mesh.clone().into(),
         transform: Transform::from_translation(Vec3::new(pos.x, pos.y, 9.0)).with_scale(Vec3::new(0.55, 0.1, 1.0)),
         ..Default::default()
-    });
+    }, ProgressBar));
     progress.others.push(bg.id());
     commands.spawn((
         MaterialMesh2dBundle {
@@ -96,38 +110,38 @@ pub fn start_build_progress(
             mesh: progress_stuff.mesh.clone().into(),
             transform: Transform::from_translation(Vec3::new(pos.x, pos.y, 10.0)).with_scale(Vec3::new(0.0, 0.03, 1.0)),
             ..Default::default()
-        }, progress
+        }, progress, ProgressBar
     ));
 }
 
 fn update_destroy(
-    time: Res<Time>,
+    clock: Res<GameClock>,
     mut commands: Commands,
     mut query: Query<(Entity, &mut Transform, &mut DestroyProgress)>,
-    mut players: Query<&mut Player>,
-    input: Res<Input<KeyCode>>,
+    mut inventory: PlayerInventory,
+    input: Res<GameInput>,
 ) {
 
     // cancel all when player moves
-    if input.any_pressed(vec![KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D]) {
+    if input.any_pressed([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D]) {
         for (entity, _, progress) in &mut query {
             for other in progress.others.iter() {
                 commands.entity(*other).despawn();
             }
             commands.entity(entity).despawn();
         }
+        return; // the despawns only happen later, cancelled progress must not complete
     }
 
     // update progress bar
     for (entity, mut transform, progress) in &mut query {
-        let progress_time = time.elapsed_seconds() - progress.start_time;
+        let progress_time = clock.seconds() - progress.start_time;
         let progress_percent = progress_time / progress.time_to_destroy;
         transform.scale.x = 0.5 * progress_percent;
         if progress_percent >= 1.0 {
             // completed!
 
-            let mut player = players.iter_mut().next().expect("no player found");
-            player.inventory = player.inventory + progress.get_inv;
+            inventory.add(&progress.get_inv, ChangeReason::Gathered);
 
             for other in progress.others.iter() {
                 commands.entity(*other).despawn();
@@ -139,26 +153,30 @@ fn update_destroy(
 }
 
 fn update_build(
-    time: Res<Time>,
+    clock: Res<GameClock>,
     mut commands: Commands,
     mut query: Query<(Entity, &mut Transform, &mut BuildProgress)>,
-    input: Res<Input<KeyCode>>,
-    tile_assets: Res<TileAssets>
+    input: Res<GameInput>,
+    tile_sprites: TileSprites,
+    mut finished_events: EventWriter<BuildFinished>,
+    mut inventory: PlayerInventory,
 ) {
 
     // cancel all when player moves
-    if input.any_pressed(vec![KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D]) {
+    if input.any_pressed([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D]) {
         for (entity, _, progress) in &mut query {
+            inventory.add(&progress.price_inv, ChangeReason::Refunded);
             for other in progress.others.iter() {
                 commands.entity(*other).despawn();
             }
             commands.entity(entity).despawn();
         }
+        return; // the despawns only happen later, cancelled builds must not complete
     }
 
     // update progress bar
     for (entity, mut transform, progress) in &mut query {
-        let progress_time = time.elapsed_seconds() - progress.start_time;
+        let progress_time = clock.seconds() - progress.start_time;
         let progress_percent = progress_time / progress.time_to_build;
         transform.scale.x = 0.5 * progress_percent;
         if progress_percent >= 1.0 {
@@ -174,10 +192,14 @@ fn update_build(
                 Buildable::House => todo!("implement house spawn"),
                 Buildable::Ship => todo!("implement ship spawn"),
             };
+            let tile_pos = WorldPos::from(transform.translation).tile_pos();
+            let object = GameObject { tile_id };
             commands.spawn((
-                create_bundle_for_tile(transform.translation.x as usize, transform.translation.y as usize, tile_id, 2.0, &*tile_assets),
-                GameObject { tile_id: tile_id },
+                tile_sprites.bundle(tile_pos, tile_id, default(), object.z()),
+                tile_pos,
+                object,
             ));
+            finished_events.send(BuildFinished { buildable: progress.buildable, pos: tile_pos });
         }
     }
 }
@@ -0,0 +1,331 @@
+use std::{collections::HashMap, io::{Cursor, Read}};
+
+use bevy::{
+    prelude::*,
+    asset::{io::Reader, AssetLoader, AsyncReadExt, BoxedFuture, LoadContext},
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{CompressedImageFormats, ImageSampler, ImageType, TextureError, TextureFormatPixelInfo},
+    },
+};
+use derive_more::{Display, From};
+use serde::Deserialize;
+use zip::{ZipArchive, result::ZipError};
+
+use crate::{
+    game_object::GameObject,
+    game_tile::{GameTile, TileOrientation},
+    map_source::{MapObject, MapSource, MapTile, Properties},
+};
+
+const BASE_LAYER: i32 = 2;
+const ENTITY_LAYER: i32 = 1;
+
+#[derive(Asset, TypePath)]
+pub struct PyxelFile {
+    pub tileswide: i32, // number of tiles in tilemap in x direction, e.g. 10
+    pub tileshigh: i32, // number of tiles in tilemap in y direction, e.g. 12
+    pub tilewidth: i32,  // width  of single tile in pixels, e.g. 32
+    pub tileheight: i32, // height of single tile in pixels, e.g. 32
+    pub layers: Vec<PyxelLayer>,
+    pub tileset: Handle<Image>, // tileset assembled from the tile images in the archive
+}
+
+pub struct PyxelLayer {
+    pub number: i32,
+    pub tiles: Vec<PyxelTile>,
+}
+
+pub struct PyxelTile {
+    pub x: i32,
+    pub y: i32,
+    pub tile: i32, // index of tile in tileset, or -1 for empty / custom tile
+    pub flip_x: bool, // mirrored horizontally, applied before rotation
+    pub rot: i32, // clockwise quarter turns: 0, 1, 2, 3
+}
+
+impl PyxelTile {
+    pub fn game_tile(&self) -> GameTile {
+        GameTile {
+            tile_id: self.tile,
+            orientation: TileOrientation { flip_x: self.flip_x, rot: self.rot.rem_euclid(4) as u8 },
+        }
+    }
+}
+
+impl PyxelFile {
+    /// empty if there is no such layer
+    fn placed_tiles(&self, number: i32) -> impl Iterator<Item = &PyxelTile> {
+        self.layers.iter()
+            .filter(move |layer| layer.number == number)
+            .flat_map(|layer| &layer.tiles)
+            .filter(|tile| tile.tile != -1)
+    }
+}
+
+impl MapSource for PyxelFile {
+    fn tile_size(&self) -> Vec2 {
+        Vec2::new(self.tilewidth as f32, self.tileheight as f32)
+    }
+
+    fn tileset(&self) -> Handle<Image> {
+        self.tileset.clone()
+    }
+
+    fn atlas_grid(&self) -> (usize, usize) {
+        (self.tileswide as usize, self.tileshigh as usize)
+    }
+
+    fn base_tiles(&self) -> Vec<MapTile> {
+        self.placed_tiles(BASE_LAYER)
+            .map(|tile| MapTile { x: tile.x, y: tile.y, tile: tile.game_tile() })
+            .collect()
+    }
+
+    fn objects(&self) -> Vec<MapObject> {
+        self.placed_tiles(ENTITY_LAYER)
+            .map(|tile| MapObject { x: tile.x, y: tile.y, object: GameObject { tile_id: tile.tile } })
+            .collect()
+    }
+
+    fn tile_properties(&self, _tile_id: i32) -> Option<&Properties> {
+        None // Pyxel Edit has no custom properties
+    }
+}
+
+// docData.json inside the .pyxel zip archive, only the parts we need
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocData {
+    tileset: DocTileset,
+    canvas: DocCanvas,
+}
+
+impl DocData {
+    /// Catches what would otherwise divide by 0 or leave the map without terrain
+    fn check(&self) -> Result<(), PyxelLoaderError> {
+        if self.tileset.tiles_wide == 0 {
+            return Err(PyxelLoaderError::ZeroSize("tileset.tilesWide"));
+        }
+        if self.canvas.tile_width <= 0 {
+            return Err(PyxelLoaderError::ZeroSize("canvas.tileWidth"));
+        }
+        if !self.canvas.layers.contains_key(&BASE_LAYER) {
+            return Err(PyxelLoaderError::MissingLayer(BASE_LAYER));
+        }
+        if !self.canvas.layers.contains_key(&ENTITY_LAYER) {
+            warn!("the map has no layer {ENTITY_LAYER}, it starts without objects");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocTileset {
+    tile_width: usize,
+    tile_height: usize,
+    num_tiles: usize,
+    tiles_wide: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocCanvas {
+    width: i32,      // in pixels
+    tile_width: i32, // in pixels
+    tile_height: i32,
+    layers: HashMap<i32, DocLayer>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocLayer {
+    name: String,
+    tile_refs: HashMap<i32, DocTileRef>, // key: y * tiles per canvas row + x
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocTileRef {
+    index: i32,
+    #[serde(default)]
+    flip_x: bool,
+    #[serde(default)]
+    rot: i32,
+}
+
+#[derive(Debug, Display, From)]
+pub enum PyxelLoaderError {
+    #[display(fmt = "could not read .pyxel file: {}", _0)]
+    Io(std::io::Error),
+    #[display(fmt = "could not unzip .pyxel file: {}", _0)]
+    Zip(ZipError),
+    #[display(fmt = "could not parse docData.json: {}", _0)]
+    Json(serde_json::Error),
+    #[display(fmt = "could not decode tile image: {}", _0)]
+    Texture(TextureError),
+    #[display(fmt = "tile{}.png has the wrong size or pixel format", _0)]
+    #[from(ignore)]
+    InvalidTile(usize),
+    #[display(fmt = "docData.json: {} should not be 0", _0)]
+    #[from(ignore)]
+    ZeroSize(&'static str),
+    #[display(fmt = "the map has no layer {}, the terrain goes there", _0)]
+    #[from(ignore)]
+    MissingLayer(i32),
+}
+
+impl std::error::Error for PyxelLoaderError {}
+
+/// Loads Pyxel Edit's native `.pyxel` files (zip archives with `docData.json` and one png per tile).
+/// The tileset image is available as the labeled asset `"tileset"` and as [`PyxelFile::tileset`].
+#[derive(Default)]
+pub struct PyxelLoader;
+
+impl AssetLoader for PyxelLoader {
+    type Asset = PyxelFile;
+    type Settings = ();
+    type Error = PyxelLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<PyxelFile, PyxelLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+
+            let doc_data: DocData = serde_json::from_slice(&read_entry(&mut archive, "docData.json")?)?;
+            doc_data.check()?;
+
+            let tileset = load_tileset(&mut archive, &doc_data.tileset)?;
+            let tileset = load_context.add_labeled_asset("tileset".to_string(), tileset);
+
+            let tiles_per_row = doc_data.canvas.width / doc_data.canvas.tile_width;
+            let mut layers = doc_data.canvas.layers.into_iter()
+                .map(|(number, layer)| {
+                    debug!("pyxel layer {}: {}", number, layer.name);
+                    let mut tiles = layer.tile_refs.into_iter()
+                        .map(|(index, tile_ref)| PyxelTile {
+                            x: index % tiles_per_row,
+                            y: index / tiles_per_row,
+                            tile: tile_ref.index,
+                            flip_x: tile_ref.flip_x,
+                            rot: tile_ref.rot,
+                        })
+                        .collect::<Vec<_>>();
+                    tiles.sort_by_key(|tile| (tile.y, tile.x));
+                    PyxelLayer { number, tiles }
+                })
+                .collect::<Vec<_>>();
+            layers.sort_by_key(|layer| layer.number);
+
+            let tileswide = doc_data.tileset.tiles_wide as i32;
+            let tileshigh = doc_data.tileset.num_tiles.div_ceil(doc_data.tileset.tiles_wide) as i32;
+            Ok(PyxelFile {
+                tileswide,
+                tileshigh,
+                tilewidth: doc_data.canvas.tile_width,
+                tileheight: doc_data.canvas.tile_height,
+                layers,
+                tileset,
+            })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["pyxel"]
+    }
+}
+
+fn read_entry(archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str) -> Result<Vec<u8>, PyxelLoaderError> {
+    let mut entry = archive.by_name(name)?;
+    let mut bytes = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Pyxel Edit stores each tile as its own png, stitch them together like the tileset export would
+fn load_tileset(archive: &mut ZipArchive<Cursor<Vec<u8>>>, tileset: &DocTileset) -> Result<Image, PyxelLoaderError> {
+    let DocTileset { tile_width, tile_height, num_tiles, tiles_wide } = *tileset;
+    let tiles_high = num_tiles.div_ceil(tiles_wide);
+    let (width, height) = (tiles_wide * tile_width, tiles_high * tile_height);
+    let format = TextureFormat::Rgba8UnormSrgb;
+    let pixel_size = format.pixel_size();
+
+    let mut data = vec![0; width * height * pixel_size];
+    for i in 0..num_tiles {
+        let tile = Image::from_buffer(
+            &read_entry(archive, &format!("tile{i}.png"))?,
+            ImageType::Extension("png"),
+            CompressedImageFormats::NONE,
+            true,
+            ImageSampler::Default,
+        )?;
+        let tile = tile.convert(format).ok_or(PyxelLoaderError::InvalidTile(i))?;
+        let size = tile.texture_descriptor.size;
+        if size.width as usize != tile_width || size.height as usize != tile_height {
+            return Err(PyxelLoaderError::InvalidTile(i));
+        }
+
+        let (x, y) = (i % tiles_wide * tile_width, i / tiles_wide * tile_height);
+        let row_len = tile_width * pixel_size;
+        for row in 0..tile_height {
+            let start = ((y + row) * width + x) * pixel_size;
+            data[start..start + row_len].copy_from_slice(&tile.data[row * row_len..(row + 1) * row_len]);
+        }
+    }
+
+    Ok(Image::new(
+        Extent3d { width: width as u32, height: height as u32, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        format,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc_data(tiles_wide: usize, tile_width: i32, layers: &str) -> DocData {
+        serde_json::from_str(&format!(r#"{{
+            "tileset": {{ "tileWidth": 16, "tileHeight": 16, "numTiles": 4, "tilesWide": {tiles_wide} }},
+            "canvas": {{ "width": 64, "tileWidth": {tile_width}, "tileHeight": 16, "layers": {{ {layers} }} }}
+        }}"#)).unwrap()
+    }
+
+    const BOTH_LAYERS: &str = r#""1": { "name": "objects", "tileRefs": {} }, "2": { "name": "terrain", "tileRefs": {} }"#;
+
+    #[test]
+    fn doc_data_check() {
+        assert!(doc_data(2, 16, BOTH_LAYERS).check().is_ok());
+        assert!(doc_data(2, 16, r#""2": { "name": "terrain", "tileRefs": {} }"#).check().is_ok());
+        assert!(matches!(doc_data(0, 16, BOTH_LAYERS).check(), Err(PyxelLoaderError::ZeroSize(_))));
+        assert!(matches!(doc_data(2, 0, BOTH_LAYERS).check(), Err(PyxelLoaderError::ZeroSize(_))));
+        assert!(matches!(
+            doc_data(2, 16, r#""1": { "name": "objects", "tileRefs": {} }"#).check(),
+            Err(PyxelLoaderError::MissingLayer(BASE_LAYER)),
+        ));
+    }
+
+    #[test]
+    fn missing_layers_have_no_tiles() {
+        let tile = PyxelTile { x: 1, y: 2, tile: 5, flip_x: false, rot: 0 };
+        let file = PyxelFile {
+            tileswide: 2,
+            tileshigh: 2,
+            tilewidth: 16,
+            tileheight: 16,
+            layers: vec![PyxelLayer { number: BASE_LAYER, tiles: vec![tile] }],
+            tileset: default(),
+        };
+        assert_eq!(file.base_tiles().len(), 1);
+        assert!(file.objects().is_empty());
+    }
+}
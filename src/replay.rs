@@ -0,0 +1,319 @@
+use std::{collections::HashSet, path::{Path, PathBuf}};
+
+use bevy::{input::{keyboard::KeyboardInput, ButtonState}, prelude::*};
+use derive_more::{Display, From};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    crafting::{Buildable, CraftingState},
+    save_game::{SaveGame, WorldRestore, WorldSnapshot, SAVE_VERSION},
+};
+
+/// Bump when the format changes, old recordings are not migrated
+pub const RECORDING_VERSION: u32 = 1;
+
+/// Gameplay runs in `FixedUpdate` at this rate, independent of the frame rate
+pub const TICKS_PER_SECOND: f64 = 60.0;
+
+pub const REPLAY_DIR: &str = "replays";
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(TICKS_PER_SECOND));
+        app.init_resource::<GameClock>();
+        app.init_resource::<GameInput>();
+        app.init_resource::<GameRng>();
+        app.init_resource::<PendingInput>();
+        app.init_resource::<Replay>();
+        app.configure_sets(FixedUpdate, (TickSet::Input, TickSet::Gameplay, TickSet::Clock).chain());
+        app.add_systems(PreUpdate, queue_input);
+        app.add_systems(FixedUpdate, (
+            apply_input.in_set(TickSet::Input),
+            advance_clock.in_set(TickSet::Clock),
+        ).run_if(in_state(AppState::Playing)));
+        app.add_systems(Update, (toggle_recording, start_replay).chain().run_if(in_state(AppState::Playing)));
+    }
+}
+
+/// Order of the systems in `FixedUpdate`, gameplay systems go into `TickSet::Gameplay`
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TickSet {
+    Input,
+    Gameplay,
+    Clock,
+}
+
+/// Counts fixed steps while playing. Gameplay measures time with it instead of `Time`,
+/// so a replay sees exactly the same times as the recorded session.
+#[derive(Resource, Debug, Default)]
+pub struct GameClock {
+    pub tick: u64,
+}
+
+impl GameClock {
+    pub fn seconds(&self) -> f32 {
+        (self.tick as f64 / TICKS_PER_SECOND) as f32
+    }
+}
+
+/// Keyboard state for gameplay, like `Input<KeyCode>` but updated once per tick,
+/// either from the keyboard or from a replay
+#[derive(Resource, Debug, Default)]
+pub struct GameInput {
+    pressed: HashSet<KeyCode>,
+    just_pressed: HashSet<KeyCode>,
+}
+
+impl GameInput {
+    pub fn pressed(&self, key: KeyCode) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    pub fn any_pressed(&self, keys: impl IntoIterator<Item = KeyCode>) -> bool {
+        keys.into_iter().any(|key| self.pressed(key))
+    }
+
+    /// pressed during this tick
+    pub fn just_pressed(&self, key: KeyCode) -> bool {
+        self.just_pressed.contains(&key)
+    }
+
+    fn apply(&mut self, key: KeyCode, pressed: bool) {
+        if !pressed {
+            self.pressed.remove(&key);
+        } else if self.pressed.insert(key) {
+            self.just_pressed.insert(key);
+        }
+    }
+}
+
+/// Randomness of gameplay, seeded so replays make the same choices
+#[derive(Resource, Deref, DerefMut)]
+pub struct GameRng(pub StdRng);
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng(StdRng::seed_from_u64(0))
+    }
+}
+
+/// Key events since the last tick, a frame can run no tick at all
+#[derive(Resource, Default)]
+struct PendingInput(Vec<(KeyCode, bool)>);
+
+#[derive(Resource, Default)]
+enum Replay {
+    #[default]
+    Off,
+    Recording(Recording),
+    Playing { recording: Recording, next: usize },
+}
+
+/// A session from `start` on: the state of the world when recording started and every key event,
+/// stamped with the tick it was applied in
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub seed: u64, // of the world and of `GameRng`
+    pub ticks_per_second: f64,
+    pub start_tick: u64,
+    pub end_tick: u64,
+    pub start: SaveGame,
+    pub recipe: Buildable,
+    pub held_keys: Vec<KeyCode>, // pressed before recording started
+    pub inputs: Vec<RecordedInput>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RecordedInput {
+    pub tick: u64,
+    pub key: KeyCode,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Display, From)]
+pub enum ReplayError {
+    #[display(fmt = "could not access the recording: {_0}")]
+    Io(std::io::Error),
+    #[display(fmt = "invalid recording: {_0}")]
+    Json(serde_json::Error),
+    #[display(fmt = "recording version {_0} doesn't match this game ({RECORDING_VERSION})")]
+    #[from(ignore)]
+    UnsupportedVersion(u32),
+    #[display(fmt = "recorded at {_0} ticks per second, the game runs at {TICKS_PER_SECOND}")]
+    #[from(ignore)]
+    WrongTickRate(f64),
+}
+
+impl std::error::Error for ReplayError {}
+
+impl Recording {
+    pub fn write(&self, path: &Path) -> Result<(), ReplayError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, ReplayError> {
+        let recording: Recording = serde_json::from_slice(&std::fs::read(path)?)?;
+        if recording.version != RECORDING_VERSION || recording.start.version != SAVE_VERSION {
+            return Err(ReplayError::UnsupportedVersion(recording.version));
+        }
+        if recording.ticks_per_second != TICKS_PER_SECOND {
+            return Err(ReplayError::WrongTickRate(recording.ticks_per_second));
+        }
+        Ok(recording)
+    }
+}
+
+pub fn last_recording_path() -> PathBuf {
+    Path::new(REPLAY_DIR).join("last.json")
+}
+
+/// Outside of `Playing` only releases are kept, so keys let go while paused don't stay pressed
+fn queue_input(mut key_events: EventReader<KeyboardInput>, state: Res<State<AppState>>, mut pending: ResMut<PendingInput>) {
+    for event in key_events.read() {
+        let Some(key) = event.key_code else { continue };
+        let pressed = event.state == ButtonState::Pressed;
+        if pressed && *state.get() != AppState::Playing {
+            continue;
+        }
+        pending.0.push((key, pressed));
+    }
+}
+
+/// Feeds the keyboard into `GameInput` and the recording, or the recorded keys while replaying
+fn apply_input(
+    mut pending: ResMut<PendingInput>,
+    mut input: ResMut<GameInput>,
+    mut replay: ResMut<Replay>,
+    clock: Res<GameClock>,
+) {
+    input.just_pressed.clear();
+    let live = std::mem::take(&mut pending.0);
+    match &mut *replay {
+        Replay::Off => {
+            for (key, pressed) in live {
+                input.apply(key, pressed);
+            }
+        }
+        Replay::Recording(recording) => {
+            for (key, pressed) in live {
+                recording.inputs.push(RecordedInput { tick: clock.tick, key, pressed });
+                input.apply(key, pressed);
+            }
+        }
+        Replay::Playing { recording, next } => {
+            // the keyboard is ignored until the replay is over
+            while let Some(recorded) = recording.inputs.get(*next).filter(|recorded| recorded.tick <= clock.tick) {
+                input.apply(recorded.key, recorded.pressed);
+                *next += 1;
+            }
+            if clock.tick >= recording.end_tick {
+                info!("replay finished after {} ticks", recording.end_tick - recording.start_tick);
+                input.pressed.clear();
+                *replay = Replay::Off;
+            }
+        }
+    }
+}
+
+fn advance_clock(mut clock: ResMut<GameClock>) {
+    clock.tick += 1;
+}
+
+/// [F6] starts recording and stops it again
+fn toggle_recording(
+    keys: Res<Input<KeyCode>>,
+    mut replay: ResMut<Replay>,
+    input: Res<GameInput>,
+    mut rng: ResMut<GameRng>,
+    clock: Res<GameClock>,
+    crafting_state: Res<CraftingState>,
+    snapshot: WorldSnapshot,
+) {
+    if !keys.just_pressed(KeyCode::F6) {
+        return;
+    }
+    match std::mem::take(&mut *replay) {
+        Replay::Off => {
+            let start = snapshot.save_game();
+            let seed = start.seed;
+            // the replay can't restore the generator state from before recording, so both start fresh
+            rng.0 = StdRng::seed_from_u64(seed);
+            *replay = Replay::Recording(Recording {
+                version: RECORDING_VERSION,
+                seed,
+                ticks_per_second: TICKS_PER_SECOND,
+                start_tick: clock.tick,
+                end_tick: clock.tick,
+                start,
+                recipe: crafting_state.recipe,
+                held_keys: input.pressed.iter().copied().collect(),
+                inputs: vec![],
+            });
+            info!("recording started");
+        }
+        Replay::Recording(mut recording) => {
+            recording.end_tick = clock.tick;
+            let path = last_recording_path();
+            match recording.write(&path) {
+                Ok(()) => info!("recorded {} ticks to {}", recording.end_tick - recording.start_tick, path.display()),
+                Err(err) => error!("saving the recording to {} failed: {err}", path.display()),
+            }
+        }
+        playing @ Replay::Playing { .. } => *replay = playing,
+    }
+}
+
+/// [F7] replays the last recording
+fn start_replay(
+    keys: Res<Input<KeyCode>>,
+    mut replay: ResMut<Replay>,
+    mut input: ResMut<GameInput>,
+    mut rng: ResMut<GameRng>,
+    mut crafting_state: ResMut<CraftingState>,
+    mut restore: WorldRestore,
+) {
+    if !keys.just_pressed(KeyCode::F7) || !matches!(*replay, Replay::Off) {
+        return;
+    }
+    let path = last_recording_path();
+    let recording = match Recording::read(&path) {
+        Ok(recording) => recording,
+        Err(err) => {
+            error!("replaying {} failed: {err}", path.display());
+            return;
+        }
+    };
+    restore.set_tick(recording.start_tick);
+    rng.0 = StdRng::seed_from_u64(recording.seed);
+    crafting_state.recipe = recording.recipe;
+    input.pressed = recording.held_keys.iter().copied().collect();
+    input.just_pressed.clear();
+    restore.restore(&recording.start);
+    info!("replaying {}", path.display());
+    *replay = Replay::Playing { recording, next: 0 };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn systems_have_no_conflicting_access() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        app.add_state::<AppState>();
+        app.add_event::<KeyboardInput>();
+        app.init_resource::<Input<KeyCode>>();
+        app.add_plugins(ReplayPlugin);
+        app.update();
+    }
+}
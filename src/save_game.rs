@@ -0,0 +1,335 @@
+use std::{path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use derive_more::{Display, From};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    crafting::Buildable,
+    game_object::GameObject,
+    game_tile::{Corner, GameTile, MapData},
+    multi_vec::{self, MultiVec},
+    items::{ChangeReason, Inventory, PlayerInventory},
+    player::Player,
+    progress::{self, BuildProgress, ProgressStuff},
+    replay::GameClock,
+    tile_metadata::TileMetadata,
+    tile_pos::TilePos,
+    tile_world::{clear_world, create_bundle_for_tile, MapConfig, TileAssets, TileMetrics, WorldEntityFilter},
+};
+
+/// Bump when the format changes and add a step to `migrate`
+pub const SAVE_VERSION: u32 = 2;
+
+pub const SAVE_DIR: &str = "saves";
+pub const QUICKSAVE_SLOT: &str = "quicksave";
+
+/// longest edge of the map thumbnail in pixels
+const THUMBNAIL_SIZE: usize = 64;
+
+pub struct SaveGamePlugin;
+
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveWorld>();
+        app.add_event::<LoadWorld>();
+        app.add_systems(Update, (
+            quicksave_keys.run_if(in_state(AppState::Playing)),
+            save_world.run_if(in_state(AppState::Playing).or_else(in_state(AppState::Paused))),
+            load_world.run_if(in_state(AppState::Playing)),
+        ).chain());
+    }
+}
+
+/// Writes the current world to `path`
+#[derive(Event, Debug, Clone)]
+pub struct SaveWorld {
+    pub path: PathBuf,
+}
+
+/// Replaces the current world with the one saved at `path`
+#[derive(Event, Debug, Clone)]
+pub struct LoadWorld {
+    pub path: PathBuf,
+}
+
+/// Everything needed to continue a game. The whole tile grid is stored, so terraformed tiles
+/// come back exactly without running the generator again.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SaveGame {
+    pub version: u32,
+    #[serde(default)]
+    pub saved_at: u64, // seconds since the unix epoch
+    #[serde(default, with = "multi_vec::runs")]
+    pub thumbnail: MultiVec<[u8; 3]>, // colour of the terrain, y pointing up like the map
+    pub seed: u64,
+    pub size: usize,
+    #[serde(with = "multi_vec::runs")]
+    pub tiles: MultiVec<i32>, // packed tiles, -1 where nothing was generated
+    pub objects: Vec<SavedObject>,
+    pub player: [f32; 2],
+    pub inventory: Inventory,
+    pub builds: Vec<SavedBuild>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedObject {
+    pub tile_id: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A `BuildProgress` that was running while saving
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedBuild {
+    pub buildable: Buildable,
+    pub price: Inventory,
+    pub x: f32,
+    pub y: f32,
+    pub elapsed: f32, // seconds built so far
+    pub time_to_build: f32,
+}
+
+/// The part of a `SaveGame` shown in the slot lists. Also written next to the save as `<slot>.meta`,
+/// so listing the slots doesn't have to read whole worlds.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SlotHeader {
+    pub saved_at: u64,
+    #[serde(with = "multi_vec::runs")]
+    pub thumbnail: MultiVec<[u8; 3]>,
+}
+
+#[derive(Debug, Display, From)]
+pub enum SaveError {
+    #[display(fmt = "could not access the save file: {_0}")]
+    Io(std::io::Error),
+    #[display(fmt = "invalid save file: {_0}")]
+    Json(serde_json::Error),
+    #[display(fmt = "save file version {_0} is newer than this game ({SAVE_VERSION})")]
+    #[from(ignore)]
+    UnsupportedVersion(u32),
+}
+
+impl std::error::Error for SaveError {}
+
+impl SaveGame {
+    pub fn write(&self, path: &Path) -> Result<(), SaveError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        replace_file(path, &serde_json::to_vec(self)?)?;
+        let header = SlotHeader { saved_at: self.saved_at, thumbnail: self.thumbnail.clone() };
+        replace_file(&header_path(path), &serde_json::to_vec(&header)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, SaveError> {
+        let mut json: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?)?;
+        migrate(&mut json)?;
+        Ok(serde_json::from_value(json)?)
+    }
+}
+
+impl SlotHeader {
+    /// Reads the header of the save at `save_path`, saves from before there were headers are read whole
+    pub fn read(save_path: &Path) -> Result<Self, SaveError> {
+        match std::fs::read(header_path(save_path)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let save_game = SaveGame::read(save_path)?;
+                Ok(SlotHeader { saved_at: save_game.saved_at, thumbnail: save_game.thumbnail })
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+fn header_path(save_path: &Path) -> PathBuf {
+    save_path.with_extension("meta")
+}
+
+/// Writes next to the old file first, so a crash doesn't leave a broken one
+fn replace_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, contents)?;
+    std::fs::rename(temp_path, path)
+}
+
+/// Upgrades older save files to `SAVE_VERSION`, step by step
+fn migrate(json: &mut serde_json::Value) -> Result<(), SaveError> {
+    let version = json.get("version").and_then(|version| version.as_u64()).unwrap_or(0) as u32;
+    if version > SAVE_VERSION {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+    if version < 2 {
+        // version 2 added `saved_at` and `thumbnail`, the defaults are fine
+        json["version"] = 2.into();
+    }
+    Ok(())
+}
+
+/// Where the save slot `name` is stored
+pub fn slot_path(name: &str) -> PathBuf {
+    Path::new(SAVE_DIR).join(format!("{name}.json"))
+}
+
+/// [F5] quicksave, [F9] quickload
+fn quicksave_keys(
+    input: Res<Input<KeyCode>>,
+    mut save_events: EventWriter<SaveWorld>,
+    mut load_events: EventWriter<LoadWorld>,
+) {
+    if input.just_pressed(KeyCode::F5) {
+        save_events.send(SaveWorld { path: slot_path(QUICKSAVE_SLOT) });
+    }
+    if input.just_pressed(KeyCode::F9) {
+        load_events.send(LoadWorld { path: slot_path(QUICKSAVE_SLOT) });
+    }
+}
+
+/// The parts of the world that go into a `SaveGame`
+#[derive(SystemParam)]
+pub struct WorldSnapshot<'w, 's> {
+    map_config: Res<'w, MapConfig>,
+    map_data: Res<'w, MapData>,
+    tile_metadata: Res<'w, TileMetadata>,
+    objects: Query<'w, 's, (&'static GameObject, &'static TilePos)>,
+    players: Query<'w, 's, (&'static Player, &'static Transform)>,
+    builds: Query<'w, 's, (&'static BuildProgress, &'static Transform)>,
+    clock: Res<'w, GameClock>,
+}
+
+impl WorldSnapshot<'_, '_> {
+    pub fn save_game(&self) -> SaveGame {
+        let (player, player_transform) = self.players.single();
+        SaveGame {
+            version: SAVE_VERSION,
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            thumbnail: thumbnail(&self.map_data, &self.tile_metadata),
+            seed: self.map_config.seed,
+            size: self.map_data.tiles().w,
+            tiles: self.map_data.tiles().map(|tile| tile.map_or(-1, GameTile::to_packed)),
+            objects: self.objects.iter()
+                .map(|(object, pos)| SavedObject { tile_id: object.tile_id, x: pos.x, y: pos.y })
+                .collect(),
+            player: player_transform.translation.truncate().to_array(),
+            inventory: player.inventory.clone(),
+            builds: self.builds.iter()
+                .map(|(progress, transform)| SavedBuild {
+                    buildable: progress.buildable,
+                    price: progress.price_inv.clone(),
+                    x: transform.translation.x,
+                    y: transform.translation.y,
+                    elapsed: self.clock.seconds() - progress.start_time,
+                    time_to_build: progress.time_to_build,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// One pixel per tile, or per block of tiles on big maps, coloured like the terrain at its corners
+fn thumbnail(map_data: &MapData, metadata: &TileMetadata) -> MultiVec<[u8; 3]> {
+    let tiles = map_data.tiles();
+    let step = tiles.w.max(tiles.h).div_ceil(THUMBNAIL_SIZE).max(1);
+    let mut thumbnail = MultiVec::new([0; 3], tiles.w.div_ceil(step), tiles.h.div_ceil(step));
+    for (x, y, pixel) in thumbnail.enum_iter_mut() {
+        let Some(tile) = map_data.get(x * step, y * step) else { continue };
+        let colors = Corner::CLOCKWISE.into_iter()
+            .filter_map(|corner| Some(metadata.terrain(metadata.corner_type(&tile, corner)?).color()))
+            .collect::<Vec<_>>();
+        if colors.is_empty() {
+            continue;
+        }
+        *pixel = std::array::from_fn(|channel| {
+            (colors.iter().map(|color| color[channel] as usize).sum::<usize>() / colors.len()) as u8
+        });
+    }
+    thumbnail
+}
+
+fn save_world(mut events: EventReader<SaveWorld>, snapshot: WorldSnapshot) {
+    for event in events.read() {
+        match snapshot.save_game().write(&event.path) {
+            Ok(()) => info!("saved the game to {}", event.path.display()),
+            Err(err) => error!("saving to {} failed: {err}", event.path.display()),
+        }
+    }
+}
+
+/// Only while playing, the tileset and its texture atlas have to be loaded already
+fn load_world(mut events: EventReader<LoadWorld>, mut restore: WorldRestore) {
+    let Some(event) = events.read().last() else { return };
+    match SaveGame::read(&event.path) {
+        Ok(save_game) => {
+            restore.restore(&save_game);
+            info!("loaded the game from {}", event.path.display());
+        }
+        Err(err) => error!("loading {} failed: {err}", event.path.display()),
+    }
+}
+
+/// Replaces the current world with a `SaveGame`
+#[derive(SystemParam)]
+pub struct WorldRestore<'w, 's> {
+    commands: Commands<'w, 's>,
+    map_config: ResMut<'w, MapConfig>,
+    tile_assets: ResMut<'w, TileAssets>,
+    tile_metrics: Res<'w, TileMetrics>,
+    map_data: ResMut<'w, MapData>,
+    world_entities: Query<'w, 's, Entity, WorldEntityFilter>,
+    players: Query<'w, 's, &'static mut Transform, With<Player>>,
+    inventory: PlayerInventory<'w, 's>,
+    progress_stuff: Res<'w, ProgressStuff>,
+    clock: ResMut<'w, GameClock>,
+}
+
+impl WorldRestore<'_, '_> {
+    /// Rewinds the clock, call before `restore` so restored builds are timed from `tick`
+    pub fn set_tick(&mut self, tick: u64) {
+        self.clock.tick = tick;
+    }
+
+    pub fn restore(&mut self, save_game: &SaveGame) {
+        clear_world(&mut self.commands, &mut self.tile_assets, &mut self.map_data, self.world_entities.iter());
+        self.tile_assets.skip_generation();
+        self.map_config.seed = save_game.seed;
+        self.map_config.size = save_game.size;
+
+        *self.map_data = MapData::new(save_game.tiles.w, save_game.tiles.h);
+        for (x, y, &packed_tile) in save_game.tiles.enum_iter() {
+            if packed_tile != -1 {
+                self.map_data.set(x, y, GameTile::from_packed(packed_tile));
+            }
+        }
+        for object in &save_game.objects {
+            if !self.tile_metrics.atlas_contains(object.tile_id) {
+                error!("skipping the object at ({},{}), tile {} is not in the tileset", object.x, object.y, object.tile_id);
+                continue;
+            }
+            let pos = TilePos::new(object.x, object.y);
+            let game_object = GameObject { tile_id: object.tile_id };
+            self.commands.spawn((
+                create_bundle_for_tile(pos, object.tile_id, default(), game_object.z(), &self.tile_assets, &self.tile_metrics),
+                pos,
+                game_object,
+                Name::new(format!("Object {} ({},{})", object.tile_id, object.x, object.y)),
+            ));
+        }
+
+        self.inventory.replace(save_game.inventory.clone(), ChangeReason::Loaded);
+        let mut player_transform = self.players.single_mut();
+        player_transform.translation = Vec2::from_array(save_game.player).extend(player_transform.translation.z);
+
+        for build in &save_game.builds {
+            progress::start_build_progress(BuildProgress {
+                others: vec![],
+                price_inv: build.price.clone(),
+                start_time: self.clock.seconds() - build.elapsed,
+                time_to_build: build.time_to_build,
+                buildable: build.buildable,
+            }, &mut self.commands, &self.progress_stuff, Vec2::new(build.x, build.y));
+        }
+    }
+}
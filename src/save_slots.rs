@@ -0,0 +1,236 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
+
+use crate::{
+    app_state::AppState,
+    multi_vec::MultiVec,
+    progress::BuildFinished,
+    save_game::{slot_path, LoadWorld, SaveWorld, SlotHeader, WorldSnapshot, SAVE_DIR},
+};
+
+pub const AUTOSAVE_SLOT: &str = "autosave";
+
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub struct SaveSlotsPlugin;
+
+impl Plugin for SaveSlotsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin); // the inspector may have added it already
+        }
+        app.insert_resource(AutosaveTimer(Timer::new(AUTOSAVE_INTERVAL, TimerMode::Repeating)));
+        app.init_resource::<SlotList>();
+        app.add_systems(Update, (autosave_on_timer, autosave_after_build).run_if(in_state(AppState::Playing)));
+        app.add_systems(Last, autosave_on_exit.run_if(in_state(AppState::Playing).or_else(in_state(AppState::Paused))));
+        app.add_systems(OnEnter(AppState::SlotSelect), mark_slots_stale);
+        app.add_systems(OnEnter(AppState::Paused), mark_slots_stale);
+        app.add_systems(PreUpdate, refresh_slots);
+        app.add_systems(Update, slot_picker.run_if(in_state(AppState::SlotSelect)));
+        app.add_systems(Update, pause_slots.run_if(in_state(AppState::Paused)));
+    }
+}
+
+#[derive(Resource)]
+struct AutosaveTimer(Timer);
+
+/// A save file as shown in the slot lists
+struct SlotInfo {
+    name: String,
+    saved_at: u64,
+    thumbnail: Option<egui::TextureHandle>,
+}
+
+/// Save slots on disk, read again when `stale`
+#[derive(Resource, Default)]
+struct SlotList {
+    slots: Vec<SlotInfo>,
+    stale: bool,
+    new_name: String, // text field for saving to a new slot
+}
+
+/// What the player clicked in a slot list
+enum SlotAction {
+    Save(String),
+    Load(String),
+}
+
+fn autosave_on_timer(time: Res<Time>, mut timer: ResMut<AutosaveTimer>, mut events: EventWriter<SaveWorld>) {
+    if timer.0.tick(time.delta()).just_finished() {
+        events.send(SaveWorld { path: slot_path(AUTOSAVE_SLOT) });
+    }
+}
+
+fn autosave_after_build(mut finished_events: EventReader<BuildFinished>, mut events: EventWriter<SaveWorld>) {
+    let Some(finished) = finished_events.read().last() else { return };
+    info!("autosaving after building a {:?} at ({},{})", finished.buildable, finished.pos.x, finished.pos.y);
+    events.send(SaveWorld { path: slot_path(AUTOSAVE_SLOT) });
+}
+
+/// Saves right away, the app is gone before a `SaveWorld` event would be handled
+fn autosave_on_exit(mut exit_events: EventReader<AppExit>, snapshot: WorldSnapshot) {
+    if exit_events.read().last().is_none() {
+        return;
+    }
+    match snapshot.save_game().write(&slot_path(AUTOSAVE_SLOT)) {
+        Ok(()) => info!("autosaved before quitting"),
+        Err(err) => error!("autosave before quitting failed: {err}"),
+    }
+}
+
+fn mark_slots_stale(mut slot_list: ResMut<SlotList>) {
+    slot_list.stale = true;
+}
+
+/// Reads the header of every save in `SAVE_DIR`, newest first. Runs a frame after a save so the file is written.
+fn refresh_slots(mut slot_list: ResMut<SlotList>, mut contexts: EguiContexts) {
+    if !slot_list.stale {
+        return;
+    }
+    slot_list.stale = false;
+    let Ok(entries) = std::fs::read_dir(SAVE_DIR) else {
+        slot_list.slots.clear();
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+    let mut slots = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            let header = SlotHeader::read(&path)
+                .map_err(|err| warn!("skipping save slot {name}: {err}"))
+                .ok()?;
+            let thumbnail = thumbnail_image(&header.thumbnail)
+                .map(|image| ctx.load_texture(format!("slot {name}"), image, egui::TextureOptions::NEAREST));
+            Some(SlotInfo { name, saved_at: header.saved_at, thumbnail })
+        })
+        .collect::<Vec<_>>();
+    slots.sort_by_key(|slot| std::cmp::Reverse(slot.saved_at));
+    slot_list.slots = slots;
+}
+
+/// None for saves without thumbnail
+fn thumbnail_image(thumbnail: &MultiVec<[u8; 3]>) -> Option<egui::ColorImage> {
+    if thumbnail.data.is_empty() {
+        return None;
+    }
+    // images start at the top, the map at the bottom
+    let pixels = (0..thumbnail.h).rev()
+        .flat_map(|y| thumbnail.row(y).expect("row inside the thumbnail"))
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect::<Vec<_>>();
+    Some(egui::ColorImage::from_rgba_unmultiplied([thumbnail.w, thumbnail.h], &pixels))
+}
+
+fn time_ago(saved_at: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    match now.saturating_sub(saved_at) {
+        seconds if seconds < 60 => "just now".to_string(),
+        seconds if seconds < 60 * 60 => format!("{} min ago", seconds / 60),
+        seconds if seconds < 24 * 60 * 60 => format!("{} h ago", seconds / (60 * 60)),
+        seconds => format!("{} days ago", seconds / (24 * 60 * 60)),
+    }
+}
+
+/// One row per slot with thumbnail, name and age. Save buttons only if `can_save`.
+fn slot_rows(ui: &mut egui::Ui, slots: &[SlotInfo], can_save: bool) -> Option<SlotAction> {
+    let mut action = None;
+    if slots.is_empty() {
+        ui.label("No saved games");
+    }
+    for slot in slots {
+        ui.horizontal(|ui| {
+            match &slot.thumbnail {
+                Some(texture) => ui.add(egui::Image::from_texture((texture.id(), egui::vec2(64.0, 64.0)))),
+                None => ui.allocate_response(egui::vec2(64.0, 64.0), egui::Sense::hover()),
+            };
+            ui.vertical(|ui| {
+                ui.strong(&slot.name);
+                ui.label(time_ago(slot.saved_at));
+                ui.horizontal(|ui| {
+                    if ui.button("Load").clicked() {
+                        action = Some(SlotAction::Load(slot.name.clone()));
+                    }
+                    if can_save && ui.button("Save").clicked() {
+                        action = Some(SlotAction::Save(slot.name.clone()));
+                    }
+                });
+            });
+        });
+    }
+    action
+}
+
+/// letters, digits, spaces, - and _, so the name works as a file name
+fn is_valid_slot_name(name: &str) -> bool {
+    !name.trim().is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+}
+
+fn slot_picker(
+    mut contexts: EguiContexts,
+    slot_list: Res<SlotList>,
+    mut load_events: EventWriter<LoadWorld>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let mut action = None;
+    let mut new_game = false;
+    egui::Window::new("Continue")
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                action = slot_rows(ui, &slot_list.slots, false);
+            });
+            ui.separator();
+            new_game = ui.button("New game").clicked();
+        });
+    if let Some(SlotAction::Load(name)) = action {
+        load_events.send(LoadWorld { path: slot_path(&name) });
+        next_state.set(AppState::Playing);
+    } else if new_game {
+        next_state.set(AppState::Playing);
+    }
+}
+
+fn pause_slots(
+    mut contexts: EguiContexts,
+    mut slot_list: ResMut<SlotList>,
+    mut save_events: EventWriter<SaveWorld>,
+    mut load_events: EventWriter<LoadWorld>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let mut action = None;
+    egui::Window::new("Save slots")
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-20.0, 20.0))
+        .collapsible(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                action = slot_rows(ui, &slot_list.slots, true);
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut slot_list.new_name);
+                let name = slot_list.new_name.trim().to_string();
+                if ui.add_enabled(is_valid_slot_name(&name), egui::Button::new("Save new")).clicked() {
+                    action = Some(SlotAction::Save(name));
+                }
+            });
+        });
+    match action {
+        Some(SlotAction::Save(name)) => {
+            save_events.send(SaveWorld { path: slot_path(&name) });
+            slot_list.new_name.clear();
+            slot_list.stale = true;
+        }
+        Some(SlotAction::Load(name)) => {
+            load_events.send(LoadWorld { path: slot_path(&name) });
+            next_state.set(AppState::Playing);
+        }
+        None => {}
+    }
+}
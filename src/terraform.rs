@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use derive_more::Display;
+
+use crate::{
+    app_state::AppState,
+    game_tile::{Corner, MapData, TileType},
+    replay::TickSet,
+    tile_metadata::TileMetadata,
+    tile_pos::WorldPos,
+};
+
+pub struct TerraformPlugin;
+
+impl Plugin for TerraformPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Terraform>();
+        // the map changes within the tick, so collisions in later ticks see it
+        app.add_systems(FixedUpdate, apply_terraform.in_set(TickSet::Gameplay).run_if(in_state(AppState::Playing)));
+        #[cfg(feature = "cheat")]
+        app.add_systems(FixedUpdate, cheat_terraform.before(apply_terraform).in_set(TickSet::Gameplay).run_if(in_state(AppState::Playing)));
+    }
+}
+
+/// Changes the terrain at grid vertex `(x, y)`, the point where the tiles
+/// `(x-1, y-1)`, `(x, y-1)`, `(x-1, y)` and `(x, y)` meet
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Terraform {
+    pub vertex: (usize, usize),
+    pub terrain: TileType,
+}
+
+#[derive(Debug, Display)]
+pub enum TerraformError {
+    #[display(fmt = "tile ({},{}) has unknown corner terrain", "_0.0", "_0.1")]
+    UnknownCorners((usize, usize)),
+    #[display(fmt = "no tile in the tileset fits at ({},{})", "_0.0", "_0.1")]
+    NoMatchingTile((usize, usize)),
+}
+
+impl std::error::Error for TerraformError {}
+
+/// The tiles around `vertex` with the corner that touches it, only those inside the map
+fn tiles_around(vertex: (usize, usize), map_data: &MapData) -> Vec<((usize, usize), Corner)> {
+    let (x, y) = vertex;
+    [
+        (x.checked_sub(1).zip(y.checked_sub(1)), Corner::TopRight),
+        (Some(x).zip(y.checked_sub(1)), Corner::TopLeft),
+        (x.checked_sub(1).zip(Some(y)), Corner::BottomRight),
+        (Some((x, y)), Corner::BottomLeft),
+    ].into_iter()
+        .filter_map(|(pos, corner)| Some((pos?, corner)))
+        .filter(|((x, y), _)| *x < map_data.tiles().w && *y < map_data.tiles().h)
+        .collect()
+}
+
+/// Terrain at `vertex`, taken from the first tile around it
+pub fn terrain_at_vertex(
+    vertex: (usize, usize),
+    map_data: &MapData,
+    metadata: &TileMetadata,
+) -> Option<TileType> {
+    tiles_around(vertex, map_data).into_iter().find_map(|((x, y), corner)| {
+        metadata.corner_type(&map_data.get(x, y)?, corner)
+    })
+}
+
+/// Sets the terrain at `vertex` and swaps the surrounding tiles for ones that fit their new corners.
+/// Nothing changes if any of the tiles has no replacement.
+pub fn terraform(
+    vertex: (usize, usize),
+    terrain: TileType,
+    map_data: &mut MapData,
+    metadata: &TileMetadata,
+) -> Result<(), TerraformError> {
+    let mut replacements = vec![];
+    for ((x, y), touching_corner) in tiles_around(vertex, map_data) {
+        let Some(tile) = map_data.get(x, y) else { continue }; // not generated yet
+        let mut corners = [TileType(0); 4];
+        for corner in Corner::CLOCKWISE {
+            corners[corner as usize] = if corner == touching_corner {
+                terrain
+            } else {
+                metadata.corner_type(&tile, corner).ok_or(TerraformError::UnknownCorners((x, y)))?
+            };
+        }
+        let new_tile = metadata.tile_for_corners(corners).ok_or(TerraformError::NoMatchingTile((x, y)))?;
+        replacements.push((x, y, new_tile));
+    }
+
+    for (x, y, new_tile) in replacements {
+        map_data.set(x, y, new_tile);
+    }
+    Ok(())
+}
+
+fn apply_terraform(
+    mut events: EventReader<Terraform>,
+    mut map_data: ResMut<MapData>,
+    tile_metadata: Res<TileMetadata>,
+) {
+    for event in events.read() {
+        if let Err(error) = terraform(event.vertex, event.terrain, &mut map_data, &tile_metadata) {
+            info!("Can't terraform at {:?}: {}", event.vertex, error);
+        }
+    }
+}
+
+/// [T] switches the terrain at the vertex closest to the player to the next one
+#[cfg(feature = "cheat")]
+fn cheat_terraform(
+    input: Res<crate::replay::GameInput>,
+    players: Query<&Transform, With<crate::player::Player>>,
+    map_data: Res<MapData>,
+    tile_metadata: Res<TileMetadata>,
+    mut terraform_events: EventWriter<Terraform>,
+) {
+    if !input.just_pressed(KeyCode::T) || tile_metadata.terrains.is_empty() {
+        return;
+    }
+    // vertex (x, y) is the bottom left corner of tile (x, y)
+    let pos = players.single().translation.truncate() + Vec2::splat(0.5);
+    let Some(vertex) = WorldPos(pos).tile_pos().to_index() else { return };
+    let Some(terrain) = terrain_at_vertex(vertex, &map_data, &tile_metadata) else { return };
+    terraform_events.send(Terraform {
+        vertex,
+        terrain: TileType((terrain.0 + 1) % tile_metadata.terrains.len()),
+    });
+}
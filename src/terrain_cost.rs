@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+
+use crate::{
+    game_tile::{Corner, MapData},
+    tile_metadata::TileMetadata,
+    tile_pos::{TilePos, WorldPos},
+};
+
+/// A quarter of a tile, the resolution of the corner terrains.
+/// Quarter (x, y) is a corner of tile (x / 2, y / 2): the left / bottom one for even coordinates.
+pub type Quarter = (i32, i32);
+
+/// Centre of the quarter in world units
+pub fn quarter_centre((x, y): Quarter) -> WorldPos {
+    WorldPos(Vec2::new(x as f32 * 0.5 - 0.25, y as f32 * 0.5 - 0.25))
+}
+
+/// The quarter containing `pos`
+pub fn quarter_at(pos: WorldPos) -> Quarter {
+    let quarter = ((pos.0 + Vec2::splat(0.5)) * 2.0).floor();
+    (quarter.x as i32, quarter.y as i32)
+}
+
+/// Movement cost factor of the terrain in the quarter, 1.0 is normal speed.
+/// None where one can't walk: unwalkable terrain, outside of the map and cells that are not generated yet.
+pub fn quarter_cost(map_data: &MapData, metadata: &TileMetadata, (x, y): Quarter) -> Option<f32> {
+    let tile = map_data.get_at(TilePos::new(x.div_euclid(2), y.div_euclid(2)))?;
+    let corner = match (x.rem_euclid(2), y.rem_euclid(2)) {
+        (0, 0) => Corner::BottomLeft,
+        (_, 0) => Corner::BottomRight,
+        (0, _) => Corner::TopLeft,
+        _ => Corner::TopRight,
+    };
+    if !metadata.can_enter(&tile, corner).unwrap_or(true) {
+        return None;
+    }
+    Some(metadata.cost(&tile, corner).unwrap_or(1.0))
+}
+
+/// Cost at `pos`, blended bilinearly between the four nearest quarters so the speed doesn't jump at terrain borders.
+/// Quarters that can't be entered are left out.
+pub fn cost_at(pos: WorldPos, map_data: &MapData, metadata: &TileMetadata) -> f32 {
+    // quarter centres are at 0.5 * q - 0.25
+    let scaled = (pos.0 + Vec2::splat(0.25)) * 2.0;
+    let base = scaled.floor();
+    let fraction = scaled - base;
+    let mut total = 0.0;
+    let mut total_weight = 0.0;
+    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let weight_x = if dx == 0 { 1.0 - fraction.x } else { fraction.x };
+        let weight_y = if dy == 0 { 1.0 - fraction.y } else { fraction.y };
+        let quarter = (base.x as i32 + dx, base.y as i32 + dy);
+        if let Some(cost) = quarter_cost(map_data, metadata, quarter) {
+            total += weight_x * weight_y * cost;
+            total_weight += weight_x * weight_y;
+        }
+    }
+    if total_weight > 0.0 { total / total_weight } else { 1.0 }
+}
+
+/// Edge weight between two neighbouring quarters for pathfinding: the distance times the mean cost of both ends.
+/// `cost` is `quarter_cost` or something built from it, None if one of the quarters can't be entered.
+pub fn step_cost(from: Quarter, to: Quarter, cost: impl Fn(Quarter) -> Option<f32>) -> Option<f32> {
+    let distance = quarter_centre(from).0.distance(quarter_centre(to).0);
+    Some(distance * (cost(from)? + cost(to)?) / 2.0)
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+
+use crate::{
+    game_tile::{Corner, GameTile, MapData},
+    tile_world::{TileAssets, TileMetrics},
+};
+
+/// width and height of a chunk in tiles
+pub const CHUNK_SIZE: usize = 32;
+
+pub struct TileChunksPlugin;
+
+impl Plugin for TileChunksPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, update_chunks);
+    }
+}
+
+/// One mesh with all tiles of a `CHUNK_SIZE` x `CHUNK_SIZE` part of the map
+#[derive(Component, Debug)]
+pub struct TileChunk {
+    pub chunk: (usize, usize),
+}
+
+/// Meshes and material of the chunks
+#[derive(SystemParam)]
+struct ChunkAssets<'w, 's> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+    material: Local<'s, Option<(Handle<Image>, Handle<ColorMaterial>)>>,
+}
+
+impl ChunkAssets<'_, '_> {
+    /// the material showing `tileset`, made again when the tileset changes
+    fn material(&mut self, tileset: &Handle<Image>) -> Handle<ColorMaterial> {
+        if self.material.as_ref().is_none_or(|(current, _)| current != tileset) {
+            *self.material = Some((tileset.clone(), self.materials.add(ColorMaterial::from(tileset.clone()))));
+        }
+        self.material.as_ref().expect("set above").1.clone()
+    }
+}
+
+/// rebuilds the meshes of all chunks with changed tiles
+fn update_chunks(
+    mut commands: Commands,
+    mut map_data: ResMut<MapData>,
+    tile_assets: Res<TileAssets>,
+    tile_metrics: Res<TileMetrics>,
+    mut chunk_assets: ChunkAssets,
+    chunks: Query<(&TileChunk, &Mesh2dHandle)>,
+) {
+    if !map_data.is_changed() {
+        return;
+    }
+    let dirty_chunks = map_data.bypass_change_detection().take_dirty_chunks();
+    if dirty_chunks.is_empty() {
+        return;
+    }
+
+    let material = chunk_assets.material(&tile_assets.tileset);
+
+    let mut chunk_meshes = chunks.iter()
+        .map(|(chunk, mesh)| (chunk.chunk, mesh.0.clone()))
+        .collect::<HashMap<_, _>>();
+    for chunk in dirty_chunks {
+        let mesh = chunk_mesh(&map_data, chunk, &tile_metrics);
+        if let Some(handle) = chunk_meshes.get(&chunk) {
+            chunk_assets.meshes.insert(handle, mesh);
+        } else {
+            let handle = chunk_assets.meshes.add(mesh);
+            commands.spawn((
+                MaterialMesh2dBundle {
+                    mesh: handle.clone().into(),
+                    material: material.clone(),
+                    transform: Transform::from_xyz((chunk.0 * CHUNK_SIZE) as f32, (chunk.1 * CHUNK_SIZE) as f32, -1.0),
+                    ..default()
+                },
+                TileChunk { chunk },
+                Name::new(format!("Tile chunk ({},{})", chunk.0, chunk.1)),
+            ));
+            chunk_meshes.insert(chunk, handle);
+        }
+    }
+}
+
+/// A quad per generated tile, positioned relative to the chunk origin
+fn chunk_mesh(map_data: &MapData, chunk: (usize, usize), tile_metrics: &TileMetrics) -> Mesh {
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut indices = vec![];
+
+    // chunks at the map border can be smaller
+    let tiles = map_data.tiles();
+    let (start_x, start_y) = (chunk.0 * CHUNK_SIZE, chunk.1 * CHUNK_SIZE);
+    let size = (CHUNK_SIZE.min(tiles.w.saturating_sub(start_x)), CHUNK_SIZE.min(tiles.h.saturating_sub(start_y)));
+    let view = tiles.view(start_x, start_y, size.0, size.1).expect("chunk should be inside the map");
+    for (x, y, tile) in view.enum_iter() {
+        let Some(tile) = tile else { continue };
+        let centre = Vec2::new(x as f32, y as f32);
+        let first_index = positions.len() as u32;
+        for corner in Corner::CLOCKWISE {
+            let position = centre + corner.direction() * 0.5;
+            positions.push([position.x, position.y, 0.0]);
+            normals.push([0.0, 0.0, 1.0]);
+            uvs.push(atlas_uv(tile, corner, tile_metrics));
+        }
+        // counter-clockwise: top left, bottom left, bottom right and top left, bottom right, top right
+        indices.extend([0, 3, 2, 0, 2, 1].map(|i| first_index + i));
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+/// Texture coordinate in the tileset for `corner` of the placed tile, the orientation is applied by picking
+/// the corner of the tile image that ends up there. Assumes the tileset has no margin or spacing.
+fn atlas_uv(tile: &GameTile, corner: Corner, tile_metrics: &TileMetrics) -> [f32; 2] {
+    let index = tile.tile_id.max(0) as usize;
+    let (column, row) = (index % tile_metrics.atlas_columns, index / tile_metrics.atlas_columns);
+    let direction = tile.orientation.source_corner(corner).direction();
+    // image y points down
+    let u = column as f32 + (direction.x + 1.0) / 2.0;
+    let v = row as f32 + (1.0 - direction.y) / 2.0;
+    [u / tile_metrics.atlas_columns as f32, v / tile_metrics.atlas_rows as f32]
+}
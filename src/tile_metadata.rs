@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    game_tile::{Corner, GameTile, TileOrientation, TileType},
+    map_source::{MapProperty, MapSource},
+};
+
+/// `*.tileset.json`: terrains and the terrain at each corner of every tile in the tileset
+#[derive(Deserialize, Asset, TypePath)]
+pub struct TilesetFile {
+    terrains: Vec<Terrain>,
+    tiles: HashMap<i32, TileFile>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct TileFile {
+    top_left: Option<String>,
+    top_right: Option<String>,
+    bottom_right: Option<String>,
+    bottom_left: Option<String>,
+    walkable: Option<bool>, // overrides the walkability of the corner terrains, e.g. for bridges
+    cost: Option<f32>,      // overrides the movement cost of the corner terrains, e.g. for roads
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Terrain {
+    pub name: String,
+    pub walkable: bool,
+    pub cost: f32, // movement cost factor, 1.0 is normal speed
+    #[serde(default)]
+    pub color: Option<[u8; 3]>, // for map thumbnails
+}
+
+impl Terrain {
+    /// grey if the tileset doesn't say
+    pub fn color(&self) -> [u8; 3] {
+        self.color.unwrap_or([128, 128, 128])
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TileInfo {
+    pub corners: [Option<TileType>; 4], // in the order of `Corner::CLOCKWISE`
+    pub walkable: Option<bool>,
+    pub cost: Option<f32>,
+    pub tags: HashSet<String>,
+}
+
+/// Terrain metadata of the tileset in use, built from the tileset file once it is loaded
+#[derive(Resource, Default)]
+pub struct TileMetadata {
+    pub terrains: Vec<Terrain>,
+    pub tiles: HashMap<i32, TileInfo>,
+    tiles_by_corners: HashMap<[TileType; 4], GameTile>,
+}
+
+impl TileMetadata {
+    /// Tiled maps can override the tileset file with the custom tile properties
+    /// `top_left`, `top_right`, `bottom_right`, `bottom_left`, `walkable`, `cost` and `tags` (comma separated)
+    pub fn new(file: &TilesetFile, map: &dyn MapSource) -> Self {
+        let mut tile_files = file.tiles.clone();
+        let mut map_tile_ids = map.base_tiles().iter().map(|tile| tile.tile.tile_id).collect::<HashSet<_>>();
+        map_tile_ids.extend(map.objects().iter().map(|object| object.object.tile_id));
+        for tile_id in map_tile_ids {
+            let Some(properties) = map.tile_properties(tile_id) else { continue };
+            let tile_file = tile_files.entry(tile_id).or_default();
+            for (name, property) in properties {
+                match (name.as_str(), property) {
+                    ("top_left", MapProperty::String(terrain)) => tile_file.top_left = Some(terrain.clone()),
+                    ("top_right", MapProperty::String(terrain)) => tile_file.top_right = Some(terrain.clone()),
+                    ("bottom_right", MapProperty::String(terrain)) => tile_file.bottom_right = Some(terrain.clone()),
+                    ("bottom_left", MapProperty::String(terrain)) => tile_file.bottom_left = Some(terrain.clone()),
+                    ("walkable", MapProperty::Bool(walkable)) => tile_file.walkable = Some(*walkable),
+                    ("cost", MapProperty::Float(cost)) => tile_file.cost = Some(*cost as f32),
+                    ("tags", MapProperty::String(tags)) => tile_file.tags = tags.split(',').map(|tag| tag.trim().to_string()).collect(),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut metadata = TileMetadata {
+            terrains: file.terrains.clone(),
+            tiles: HashMap::new(),
+            tiles_by_corners: HashMap::new(),
+        };
+        for (tile_id, tile_file) in tile_files {
+            let corners = [&tile_file.top_left, &tile_file.top_right, &tile_file.bottom_right, &tile_file.bottom_left]
+                .map(|terrain| {
+                    let terrain = terrain.as_ref()?;
+                    let tile_type = metadata.terrain_by_name(terrain);
+                    if tile_type.is_none() {
+                        warn!("tile {} uses unknown terrain {}", tile_id, terrain);
+                    }
+                    tile_type
+                });
+            metadata.tiles.insert(tile_id, TileInfo {
+                corners,
+                walkable: tile_file.walkable,
+                cost: tile_file.cost,
+                tags: tile_file.tags.into_iter().collect(),
+            });
+        }
+
+        // unrotated tiles and lower ids win if several tiles have the same corners
+        let mut tile_ids = metadata.tiles.keys().copied()
+            .filter(|tile_id| !metadata.has_tag(*tile_id, "no_autotile"))
+            .collect::<Vec<_>>();
+        tile_ids.sort();
+        let orientations = (0..4).flat_map(|rot| [false, true].map(|flip_x| TileOrientation { flip_x, rot }));
+        for orientation in orientations {
+            for &tile_id in &tile_ids {
+                let tile = GameTile { tile_id, orientation };
+                let corners = Corner::CLOCKWISE.map(|corner| metadata.corner_type(&tile, corner));
+                if let [Some(top_left), Some(top_right), Some(bottom_right), Some(bottom_left)] = corners {
+                    metadata.tiles_by_corners.entry([top_left, top_right, bottom_right, bottom_left]).or_insert(tile);
+                }
+            }
+        }
+        metadata
+    }
+
+    /// Inverse of `corner_type`: a tile showing these terrains at its corners (in `Corner::CLOCKWISE` order).
+    /// Tiles tagged `no_autotile` are never picked.
+    pub fn tile_for_corners(&self, corners: [TileType; 4]) -> Option<GameTile> {
+        self.tiles_by_corners.get(&corners).copied()
+    }
+
+    pub fn terrain(&self, tile_type: TileType) -> &Terrain {
+        &self.terrains[tile_type.0]
+    }
+
+    pub fn terrain_by_name(&self, name: &str) -> Option<TileType> {
+        self.terrains.iter().position(|terrain| terrain.name == name).map(TileType)
+    }
+
+    /// Terrain at `corner` as displayed, i.e. with the orientation of the tile applied
+    pub fn corner_type(&self, tile: &GameTile, corner: Corner) -> Option<TileType> {
+        let source_corner = tile.orientation.source_corner(corner);
+        self.tiles.get(&tile.tile_id)?.corners[source_corner as usize]
+    }
+
+    /// None if the corner has no known terrain
+    pub fn can_enter(&self, tile: &GameTile, corner: Corner) -> Option<bool> {
+        let walkable = self.tiles.get(&tile.tile_id)?.walkable;
+        walkable.or_else(|| Some(self.terrain(self.corner_type(tile, corner)?).walkable))
+    }
+
+    /// Movement cost factor at `corner`, None if the corner has no known terrain
+    pub fn cost(&self, tile: &GameTile, corner: Corner) -> Option<f32> {
+        let cost = self.tiles.get(&tile.tile_id)?.cost;
+        cost.or_else(|| Some(self.terrain(self.corner_type(tile, corner)?).cost))
+    }
+
+    pub fn has_tag(&self, tile_id: i32, tag: &str) -> bool {
+        self.tiles.get(&tile_id).is_some_and(|tile| tile.tags.contains(tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_source::TestMap;
+
+    #[test]
+    fn shipped_tileset_corners() {
+        let file: TilesetFile = serde_json::from_str(include_str!("../assets/tilesets/Map.tileset.json")).unwrap();
+        let metadata = TileMetadata::new(&file, &TestMap::default());
+        let corners = |tile_id| metadata.tiles[&tile_id].corners.map(|corner| &metadata.terrain(corner.unwrap()).name[..]);
+
+        assert_eq!(corners(41), ["desert"; 4]);
+        // 62 is desert with a bit of mountain in the top right, like 63 mirrored
+        assert_eq!(corners(62), ["desert", "mountain", "desert", "desert"]);
+        assert_eq!(corners(63), ["mountain", "desert", "desert", "desert"]);
+        // 52 is the house sprite, not terrain
+        assert!(!metadata.tiles.contains_key(&52));
+
+        let desert = metadata.terrain_by_name("desert").unwrap();
+        assert_eq!(metadata.tile_for_corners([desert; 4]).map(|tile| tile.tile_id), Some(41));
+    }
+}
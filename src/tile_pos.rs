@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use derive_more::{Add, Sub};
+
+/// A map cell, (0, 0) is the bottom left tile and y points up like in the world.
+/// Can be negative or beyond the map, e.g. for neighbours of border tiles.
+#[derive(Component, Debug, Default, Reflect, Clone, Copy, PartialEq, Eq, Hash, Add, Sub)]
+pub struct TilePos {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A point in world units. One tile is one unit and tile centres are at whole numbers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Add, Sub)]
+pub struct WorldPos(pub Vec2);
+
+impl TilePos {
+    pub fn new(x: i32, y: i32) -> Self {
+        TilePos { x, y }
+    }
+
+    /// Position in a `MultiVec` covering the map, None for negative coordinates.
+    /// Positions beyond the map are not checked here.
+    pub fn to_index(self) -> Option<(usize, usize)> {
+        Some((usize::try_from(self.x).ok()?, usize::try_from(self.y).ok()?))
+    }
+
+    pub fn from_index(x: usize, y: usize) -> Self {
+        TilePos { x: x as i32, y: y as i32 }
+    }
+
+    pub fn offset(self, dx: i32, dy: i32) -> Self {
+        TilePos { x: self.x + dx, y: self.y + dy }
+    }
+
+    /// centre of the tile
+    pub fn world_pos(self) -> WorldPos {
+        WorldPos(Vec2::new(self.x as f32, self.y as f32))
+    }
+}
+
+impl WorldPos {
+    /// The tile containing this point. Tiles reach half a unit around their centre,
+    /// points on a border belong to the tile above / to the right, also for negative coordinates.
+    pub fn tile_pos(self) -> TilePos {
+        let cell = (self.0 + Vec2::splat(0.5)).floor();
+        TilePos { x: cell.x as i32, y: cell.y as i32 }
+    }
+
+    pub fn translation(self, z: f32) -> Vec3 {
+        self.0.extend(z)
+    }
+}
+
+impl From<Vec2> for WorldPos {
+    fn from(pos: Vec2) -> Self {
+        WorldPos(pos)
+    }
+}
+
+impl From<Vec3> for WorldPos {
+    fn from(translation: Vec3) -> Self {
+        WorldPos(translation.truncate())
+    }
+}
+
+impl From<WorldPos> for TilePos {
+    fn from(pos: WorldPos) -> Self {
+        pos.tile_pos()
+    }
+}
+
+impl From<TilePos> for IVec2 {
+    fn from(pos: TilePos) -> Self {
+        IVec2::new(pos.x, pos.y)
+    }
+}
+
+impl From<TilePos> for WorldPos {
+    fn from(pos: TilePos) -> Self {
+        pos.world_pos()
+    }
+}
+
+/// `T`s that have no `TilePos` yet
+type MissingTilePos<T> = (With<T>, Without<TilePos>);
+/// `T`s whose `Transform` changed
+type Moved<T> = (With<T>, Changed<Transform>);
+
+/// Keeps the `TilePos` of objects in sync with their `Transform`, and adds it where it is missing.
+/// Terrain tiles are no entities, `MapData` stores them by cell, so they don't need it.
+pub fn sync_tile_pos<T: Component>(
+    mut commands: Commands,
+    missing: Query<(Entity, &Transform), MissingTilePos<T>>,
+    mut moved: Query<(&Transform, &mut TilePos), Moved<T>>,
+) {
+    for (entity, transform) in &missing {
+        commands.entity(entity).insert(WorldPos::from(transform.translation).tile_pos());
+    }
+    for (transform, mut tile_pos) in &mut moved {
+        let new_pos = WorldPos::from(transform.translation).tile_pos();
+        if *tile_pos != new_pos {
+            *tile_pos = new_pos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_at(x: f32, y: f32) -> (i32, i32) {
+        let pos = WorldPos(Vec2::new(x, y)).tile_pos();
+        (pos.x, pos.y)
+    }
+
+    #[test]
+    fn tile_pos_of_negative_points() {
+        assert_eq!(tile_at(-0.49, -0.49), (0, 0));
+        assert_eq!(tile_at(-0.5, -0.5), (0, 0));
+        assert_eq!(tile_at(-0.51, -0.51), (-1, -1));
+        assert_eq!(tile_at(-1.0, -2.0), (-1, -2));
+        assert_eq!(tile_at(-1.49, 0.49), (-1, 0));
+        assert_eq!(tile_at(-1.51, 0.51), (-2, 1));
+    }
+
+    #[test]
+    fn borders_belong_to_the_tile_above_and_to_the_right() {
+        assert_eq!(tile_at(0.5, 0.5), (1, 1));
+        assert_eq!(tile_at(0.49, 0.49), (0, 0));
+        assert_eq!(tile_at(1.5, -1.5), (2, -1));
+        assert_eq!(tile_at(-2.5, 2.5), (-2, 3));
+        // tile centres round trip
+        for pos in [TilePos::new(0, 0), TilePos::new(-3, 7), TilePos::new(5, -1)] {
+            assert_eq!(pos.world_pos().tile_pos(), pos);
+        }
+    }
+
+    #[test]
+    fn to_index() {
+        assert_eq!(TilePos::new(0, 0).to_index(), Some((0, 0)));
+        assert_eq!(TilePos::new(3, 7).to_index(), Some((3, 7)));
+        assert_eq!(TilePos::new(-1, 0).to_index(), None);
+        assert_eq!(TilePos::new(0, -1).to_index(), None);
+        assert_eq!(TilePos::new(i32::MIN, 5).to_index(), None);
+        assert_eq!(TilePos::from_index(4, 2).to_index(), Some((4, 2)));
+    }
+}
@@ -1,255 +1,497 @@
-use std::{sync::{Mutex, mpsc}, collections::{HashMap, HashSet}, cmp::{min, max}, ops::Not};
+use std::{sync::{Mutex, mpsc}, collections::{BTreeSet, HashMap, HashSet}, cmp::{min, max}};
 
-use bevy::{prelude::*, asset::LoadState, sprite::collide_aabb};
+use bevy::{prelude::*, asset::{LoadState, UntypedAssetId}, ecs::system::SystemParam};
 use bevy_common_assets::json::JsonAssetPlugin;
 use rand::prelude::*;
-use serde::Deserialize;
 
 use crate::{
     multi_vec::MultiVec,
     game_tile::{
         MapData,
         GameTile,
-        TileType,
+        TileOrientation,
     },
     game_object::GameObject, player::Player, wave_function_collapse_generator::WaveFunctionCollapseGenerator,
+    map_source::{MapHandle, MapSource},
+    pyxel_file::{PyxelFile, PyxelLoader},
+    tiled_map::{TiledMap, TiledLoader},
+    tile_metadata::{TileMetadata, TilesetFile},
+    tileset_validation::{self, TilesetDiagnostics},
+    tile_chunks::TileChunk,
+    progress::{BuildProgress, ProgressBar},
+    items::{ChangeReason, ItemRegistry, PlayerInventory},
+    app_state::{AppState, ErrorMessage},
+    player_spawn,
+    tile_pos::{self, TilePos},
 };
 
+
 pub struct TileWorldPlugin;
 impl Plugin for TileWorldPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(JsonAssetPlugin::<PyxelFile>::new(&["json"])); // register .json extension (example advises for *."map.json")
+        app.init_asset::<PyxelFile>();
+        app.init_asset_loader::<PyxelLoader>(); // native .pyxel files, no export from Pyxel Edit needed
+        app.init_asset::<TiledMap>();
+        app.init_asset_loader::<TiledLoader>();
+        app.add_plugins(JsonAssetPlugin::<TilesetFile>::new(&["tileset.json"]));
+        app.init_resource::<TileMetadata>();
+        app.init_resource::<TilesetDiagnostics>();
+        app.init_resource::<TileMetrics>();
+        app.init_resource::<MapConfig>();
         app.add_systems(PreStartup, pre_setup);
-        app.add_systems(PreUpdate, generate_on_load_complete);
+        app.add_event::<RegenerateWorld>();
+        app.add_systems(PreUpdate, (
+            regenerate_on_asset_change,
+            regenerate_on_request,
+            generate_on_load_complete.run_if(in_state(AppState::Loading)),
+            place_player.run_if(in_state(AppState::Loading)),
+            spawn_generated,
+            finish_loading.run_if(in_state(AppState::Loading)),
+        ).chain());
+        #[cfg(feature = "cheat")]
+        app.add_systems(FixedUpdate, cheat_new_island.in_set(crate::replay::TickSet::Gameplay).run_if(in_state(AppState::Playing)));
         app.register_type::<GameObject>();
         app.register_type::<GameTile>();
+        app.register_type::<TilePos>();
+        app.add_systems(PostUpdate, (tile_pos::sync_tile_pos::<GameObject>, track_objects).chain());
         app.init_resource::<MapData>();
     }
     fn name(&self) -> &str { "TileWorldPlugin" }
 }
 
-#[derive(Deserialize, Asset, TypePath)]
-struct PyxelFile {
-    // tileswide: i32, // number of tiles in tilemap in x direction, e.g. 10
-    // tileshigh: i32, // number of tiles in tilemap in y direction, e.g. 12
-    tilewidth: i32,  // width  of single tile in pixels, e.g. 32
-    tileheight: i32, // height of single tile in pixels, e.g. 32
-    layers: Vec<PyxelLayer>,
+/// Map the world is generated from, made with Pyxel Edit (.pyxel) or Tiled (.tmx, .tmj)
+#[derive(Resource)]
+pub struct MapConfig {
+    pub path: String,
+    pub fixed: bool, // spawn the hand-made map as it is instead of using it as training data
+    pub tileset: String, // terrain metadata of the map's tileset (.tileset.json)
+    pub strict_tileset: bool, // refuse to start when the tileset validation finds problems
+    pub seed: u64,
+    pub size: usize, // edge length of the generated world in tiles, the world is square
+    pub spawn_tiles: Vec<i32>, // tile ids the player preferably starts on
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        MapConfig {
+            path: "pyxel://Map.pyxel".to_string(),
+            fixed: false,
+            tileset: "tilesets/Map.tileset.json".to_string(),
+            strict_tileset: false,
+            seed: 666,
+            size: 64,
+            spawn_tiles: vec![9],
+        }
+    }
 }
 
-#[derive(Deserialize)]
-struct PyxelLayer {
-    name: String,
-    number: i32,
-    tiles: Vec<PyxelTile>,
+/// Tile size and tileset layout of the loaded map, one tile is one world unit no matter its size in pixels
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct TileMetrics {
+    pub tile_size: Vec2, // in pixels
+    pub atlas_columns: usize,
+    pub atlas_rows: usize,
 }
 
-#[derive(Deserialize)]
-struct PyxelTile {
-    x: i32,
-    y: i32,
-    tile: i32, // index of tile in tileset, or -1 for empty / custom tile
-    // index: i32, // not sure, maybe y * width + x?
-    // #[serde(rename = "flipX")]
-    // flip_x: bool,
-    // rot: i32, // 0, 1, 2, 3
+impl Default for TileMetrics {
+    fn default() -> Self {
+        TileMetrics {
+            tile_size: Vec2::new(32.0, 32.0),
+            atlas_columns: 8,
+            atlas_rows: 8,
+        }
+    }
+}
+
+impl TileMetrics {
+    pub fn new(map: &dyn MapSource) -> Self {
+        let (atlas_columns, atlas_rows) = map.atlas_grid();
+        TileMetrics { tile_size: map.tile_size(), atlas_columns, atlas_rows }
+    }
+
+    /// whether the tileset has an image for `tile_id`
+    pub fn atlas_contains(&self, tile_id: i32) -> bool {
+        usize::try_from(tile_id).is_ok_and(|index| index < self.atlas_columns * self.atlas_rows)
+    }
+
+    /// scale that makes a tile sprite exactly one world unit big
+    pub fn sprite_scale(&self) -> Vec3 {
+        Vec3::new(1.0 / self.tile_size.x, 1.0 / self.tile_size.y, 1.0)
+    }
 }
 
 #[derive(Resource)]
 pub struct TileAssets {
-    pyxel_file: Handle<PyxelFile>,
-    tileset: Handle<Image>,
+    map: MapHandle,
+    tileset_file: Handle<TilesetFile>,
+    pub tileset: Handle<Image>,
     generation_started: bool,
+    generation_finished: bool, // the generator sent its last tile
     has_moved_player: bool,
     rx: Option<Mutex<mpsc::Receiver<(usize, usize, i32)>>>,
     texture_atlas: Handle<TextureAtlas>,
-    spawn_entities_for_base_tile: HashMap<i32, HashSet<i32>>,
+    spawn_entities_for_base_tile: HashMap<i32, BTreeSet<i32>>, // ordered, so a seed always picks the same objects
+    fixed_objects: Option<HashMap<(usize, usize), i32>>, // objects exactly as placed, only for fixed maps
 }
 
-fn single_collision(pos: Vec3, player_transform: &Transform) -> bool{
-    collide_aabb::collide(
-        pos,
-        Vec2::new(0.5, 0.5),
-        player_transform.translation,
-        player_transform.scale.truncate() * 32.0
-    ).is_some()
-}
-
-pub fn check_collision(
-    map_data: &MapData,
-    tiles: &Query<&GameTile>,
-    player_transform: &Transform,
-) -> bool {
-
-    let player_pos = player_transform.translation;
-    
-    for x_offset in -1..=1 {
-        for y_offset in -1..=1 {
-            let Some((x, y, tile)) = map_data.get_tile_at_pos(
-                Vec2::new(player_pos.x + x_offset as f32, player_pos.y + y_offset as f32), &tiles) 
-             else {
-                warn!("Couldn't get tile {} {}", (player_pos.x + x_offset as f32).round(), (player_pos.y + y_offset as f32).round());
-                return true;
-            };
-            let x = x as f32;
-            let y = y  as f32;
-            
-            if tile.top_left_type().map(TileType::can_enter).unwrap_or(true).not() && single_collision(
-                Vec3{x: x-0.25, y: y+0.25, z: 1.},
-                player_transform,
-            ) {
-                return true;
-            }
-            
-                        
-            if tile.top_right_type().map(TileType::can_enter).unwrap_or(true).not() && single_collision(
-                Vec3{x: x+0.25, y: y+0.25, z: 1.},
-                player_transform,
-            ) {
-                return true;
-            }
-            
-                        
-            if tile.bottom_left_type().map(TileType::can_enter).unwrap_or(true).not() && single_collision(
-                Vec3{x: x-0.25, y: y-0.25, z: 1.},
-                player_transform,
-            ) {
-                return true;
-            }
-            
-                        
-            if tile.bottom_right_type().map(TileType::can_enter).unwrap_or(true).not() && single_collision(
-                Vec3{x: x+0.25, y: y-0.25, z: 1.},
-                player_transform,
-            ) {
-                return true;
-            }
-        }
-    };
-    false
-}
-
-fn pre_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let pyxel_handle: Handle<PyxelFile> = asset_server.load("json/Map.json");
-    let tileset_handle: Handle<Image> = asset_server.load("textures/Map.png");
-
+fn pre_setup(mut commands: Commands, asset_server: Res<AssetServer>, map_config: Res<MapConfig>) {
     commands.insert_resource(TileAssets {
-        pyxel_file: pyxel_handle,
-        tileset: tileset_handle,
+        map: MapHandle::load(&asset_server, &map_config.path),
+        tileset_file: asset_server.load(map_config.tileset.clone()),
+        tileset: default(), // comes with the map, set once it is loaded
         generation_started: false,
+        generation_finished: false,
         texture_atlas: default(),
         has_moved_player: false,
         rx: None,
         spawn_entities_for_base_tile: HashMap::new(),
+        fixed_objects: None,
     });
 }
 
-pub fn create_bundle_for_tile(x: usize, y: usize, tile_id: i32, z: f32,
-    tile_assets: &TileAssets
+/// Tears down the current world and generates a new one with this seed and size, without restarting
+#[derive(Event, Debug, Clone, Copy)]
+pub struct RegenerateWorld {
+    pub seed: u64,
+    pub size: usize, // edge length in tiles, ignored for fixed maps
+}
+
+/// Everything that belongs to the generated world and goes away with it
+pub type WorldEntityFilter = Or<(With<GameObject>, With<TileChunk>, With<ProgressBar>)>;
+
+/// Removes the generated world so `generate_on_load_complete` starts over with the current assets.
+/// `world_entities` are despawned, e.g. objects and tile chunks.
+pub fn clear_world(
+    commands: &mut Commands,
+    tile_assets: &mut TileAssets,
+    map_data: &mut MapData,
+    world_entities: impl Iterator<Item = Entity>,
+) {
+    for entity in world_entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    *map_data = MapData::default();
+    tile_assets.rx = None; // the generator thread stops once it can't send anymore
+    tile_assets.generation_started = false;
+    tile_assets.generation_finished = false;
+    tile_assets.has_moved_player = false;
+    tile_assets.spawn_entities_for_base_tile.clear();
+    tile_assets.fixed_objects = None;
+}
+
+/// `clear_world` for a world that is thrown away for good, builds in progress refund what they reserved.
+/// A restored save brings its own builds and inventory, so `WorldRestore` clears without this.
+#[derive(SystemParam)]
+pub struct WorldReset<'w, 's> {
+    commands: Commands<'w, 's>,
+    tile_assets: ResMut<'w, TileAssets>,
+    map_data: ResMut<'w, MapData>,
+    world_entities: Query<'w, 's, Entity, WorldEntityFilter>,
+    builds: Query<'w, 's, &'static BuildProgress>,
+    inventory: PlayerInventory<'w, 's>,
+}
+
+impl WorldReset<'_, '_> {
+    fn reset(&mut self) {
+        for build in &self.builds {
+            self.inventory.add(&build.price_inv, ChangeReason::Refunded);
+        }
+        clear_world(&mut self.commands, &mut self.tile_assets, &mut self.map_data, self.world_entities.iter());
+    }
+}
+
+/// Hot reload: regenerate the world when the map or the tileset file changed on disk.
+/// Also leaves `AppState::Error` once a broken file was fixed.
+fn regenerate_on_asset_change(
+    mut pyxel_file_events: EventReader<AssetEvent<PyxelFile>>,
+    mut tiled_map_events: EventReader<AssetEvent<TiledMap>>,
+    mut tileset_file_events: EventReader<AssetEvent<TilesetFile>>,
+    mut world: WorldReset,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let map_id = world.tile_assets.map.id();
+    let map_changed = any_changed(&mut pyxel_file_events, map_id) | any_changed(&mut tiled_map_events, map_id);
+    let tileset_changed = any_changed(&mut tileset_file_events, world.tile_assets.tileset_file.id().untyped());
+
+    if map_changed || tileset_changed {
+        if world.tile_assets.generation_started {
+            info!("map or tileset changed, regenerating the world");
+            world.reset();
+        }
+        next_state.set(AppState::Loading);
+    }
+}
+
+/// reads all events, not just up to the first match
+fn any_changed<A: Asset>(events: &mut EventReader<AssetEvent<A>>, asset_id: UntypedAssetId) -> bool {
+    events.read()
+        .filter(|event| matches!(event, AssetEvent::Added { id } | AssetEvent::Modified { id } if id.untyped() == asset_id))
+        .count() > 0
+}
+
+fn regenerate_on_request(
+    mut events: EventReader<RegenerateWorld>,
+    mut map_config: ResMut<MapConfig>,
+    mut world: WorldReset,
+    mut players: Query<&mut Transform, With<Player>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let Some(event) = events.read().last() else { return };
+    info!("regenerating the world with seed {} and size {}", event.seed, event.size);
+    map_config.seed = event.seed;
+    map_config.size = event.size;
+    world.reset();
+    next_state.set(AppState::Loading);
+
+    // wait in the middle until the spawn point is chosen
+    let mut player_transform = players.single_mut();
+    let centre = (event.size / 2) as f32;
+    player_transform.translation = Vec3::new(centre, centre, player_transform.translation.z);
+}
+
+/// [N] generates a new island with a random seed
+#[cfg(feature = "cheat")]
+fn cheat_new_island(
+    input: Res<crate::replay::GameInput>,
+    mut rng: ResMut<crate::replay::GameRng>,
+    map_config: Res<MapConfig>,
+    mut events: EventWriter<RegenerateWorld>,
+) {
+    if input.just_pressed(KeyCode::N) {
+        events.send(RegenerateWorld { seed: rng.gen(), size: map_config.size });
+    }
+}
+
+/// `create_bundle_for_tile` for systems that spawn objects
+#[derive(SystemParam)]
+pub struct TileSprites<'w> {
+    tile_assets: Res<'w, TileAssets>,
+    tile_metrics: Res<'w, TileMetrics>,
+}
+
+impl TileSprites<'_> {
+    pub fn bundle(&self, pos: TilePos, tile_id: i32, orientation: TileOrientation, z: f32) -> SpriteSheetBundle {
+        create_bundle_for_tile(pos, tile_id, orientation, z, &self.tile_assets, &self.tile_metrics)
+    }
+}
+
+pub fn create_bundle_for_tile(pos: TilePos, tile_id: i32, orientation: TileOrientation, z: f32,
+    tile_assets: &TileAssets, tile_metrics: &TileMetrics,
 ) -> SpriteSheetBundle {
     SpriteSheetBundle {
         texture_atlas: tile_assets.texture_atlas.clone(),
-        sprite: TextureAtlasSprite::new(tile_id as usize),
+        sprite: TextureAtlasSprite {
+            index: tile_id as usize,
+            flip_x: orientation.flip_x,
+            ..Default::default()
+        },
         transform:
-            Transform::from_translation(Vec3::new(x as f32, y as f32, z))
-            .with_scale(Vec3::new(1.0 / 32.0, 1.0 / 32.0, 1.0)),
+            Transform::from_translation(pos.world_pos().translation(z))
+            .with_rotation(orientation.rotation())
+            .with_scale(tile_metrics.sprite_scale()),
         ..Default::default()
     }
 }
 
+/// The map and tileset files the world is generated from
+#[derive(SystemParam)]
+struct MapFiles<'w> {
+    asset_server: Res<'w, AssetServer>,
+    pyxel_files: Res<'w, Assets<PyxelFile>>,
+    tiled_maps: Res<'w, Assets<TiledMap>>,
+    tileset_files: Res<'w, Assets<TilesetFile>>,
+}
+
+/// Everything taken from the tileset once it is loaded
+#[derive(SystemParam)]
+struct TilesetResources<'w> {
+    metadata: ResMut<'w, TileMetadata>,
+    diagnostics: ResMut<'w, TilesetDiagnostics>,
+    metrics: ResMut<'w, TileMetrics>,
+    texture_atlases: ResMut<'w, Assets<TextureAtlas>>,
+}
+
 fn generate_on_load_complete(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    files: MapFiles,
     mut tile_assets: ResMut<TileAssets>,
     mut map_data: ResMut<MapData>,
-    pyxel_file_assets: Res<Assets<PyxelFile>>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    mut player_query: Query<(&Player, &mut Transform)>,
+    mut tileset: TilesetResources,
+    map_config: Res<MapConfig>,
+    mut error_message: ResMut<ErrorMessage>,
+    mut next_state: ResMut<NextState<AppState>>,
 ) {
     if !tile_assets.generation_started {
-        let pyxel_load_state = asset_server.get_load_state(tile_assets.pyxel_file.id())
+        let map_load_state = files.asset_server.get_load_state(tile_assets.map.id())
+            .expect("asset_server.get_load_state returns Option<LoadState>, should be Some");
+        let tileset_load_state = files.asset_server.get_load_state(&tile_assets.tileset_file)
             .expect("asset_server.get_load_state returns Option<LoadState>, should be Some");
 
-        if pyxel_load_state == LoadState::Loaded {
-            println!("pyxel file loaded!!!!!!11");
+        if map_load_state == LoadState::Failed || tileset_load_state == LoadState::Failed {
+            let path = if map_load_state == LoadState::Failed { &map_config.path } else { &map_config.tileset };
+            error_message.0 = format!("Could not load {}, see the log for details", path);
+            next_state.set(AppState::Error);
+            return;
+        }
+
+        if map_load_state == LoadState::Loaded && tileset_load_state == LoadState::Loaded {
+            debug!("map file loaded");
 
-            let pyxel_file = pyxel_file_assets.get(&tile_assets.pyxel_file).expect(
-                "pyxel json file should be loaded since we checked that LoadState::Loaded"
+            let map = tile_assets.map.get(&files.pyxel_files, &files.tiled_maps).expect(
+                "map file should be loaded since we checked that LoadState::Loaded"
             );
-            start_generation(pyxel_file, &mut *tile_assets, &mut *texture_atlases, &mut *map_data);
+            let tileset_file = files.tileset_files.get(&tile_assets.tileset_file).expect(
+                "tileset file should be loaded since we checked that LoadState::Loaded"
+            );
+            *tileset.metadata = TileMetadata::new(tileset_file, map);
+            *tileset.metrics = TileMetrics::new(map);
+
+            match tileset_validation::check(&tileset.metadata, map, &tileset.metrics, map_config.strict_tileset) {
+                Ok(diagnostics) => tileset.diagnostics.0 = diagnostics,
+                Err(diagnostics) => {
+                    error_message.0 = format!("Tileset {} has {} problems, see the log for details", map_config.tileset, diagnostics.len());
+                    tileset.diagnostics.0 = diagnostics;
+                    next_state.set(AppState::Error);
+                    return;
+                }
+            }
+            start_generation(map, &map_config, &mut tile_assets, &tileset.metrics, &mut tileset.texture_atlases, &mut map_data);
 
             tile_assets.generation_started = true;
         }
 
     }
+}
+
+/// Puts the player on a good spot once the whole map is generated.
+/// Runs a frame after the last tile arrived, so all objects are spawned by then.
+fn place_player(
+    mut tile_assets: ResMut<TileAssets>,
+    map_data: Res<MapData>,
+    tile_metadata: Res<TileMetadata>,
+    map_config: Res<MapConfig>,
+    objects: Query<&TilePos, With<GameObject>>,
+    mut players: Query<&mut Transform, With<Player>>,
+) {
+    if !tile_assets.generation_finished || tile_assets.has_moved_player {
+        return;
+    }
+    let object_positions = objects.iter().copied().collect::<HashSet<_>>();
+    let mut player_transform = players.single_mut();
+    match player_spawn::choose_spawn(&map_data, &tile_metadata, &object_positions, &map_config.spawn_tiles) {
+        Some((x, y)) => player_transform.translation = Vec3::new(x as f32, y as f32, player_transform.translation.z),
+        None => warn!("no walkable tile to spawn the player on"),
+    }
+    tile_assets.has_moved_player = true;
+}
 
-    spawn_generated(&mut commands, &mut *tile_assets, &mut player_query.single_mut().1, &mut *map_data);
+/// the spawn area is generated once the player has been placed. The first world is shown behind the save slots.
+fn finish_loading(
+    tile_assets: Res<TileAssets>,
+    item_registry: Res<ItemRegistry>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut offered_slots: Local<bool>,
+) {
+    if tile_assets.has_moved_player && item_registry.is_loaded() {
+        next_state.set(if *offered_slots { AppState::Playing } else { AppState::SlotSelect });
+        *offered_slots = true;
+    }
+}
+
+impl TileAssets {
+    /// After `clear_world`, for a world that was filled in some other way, e.g. loaded from a save
+    pub fn skip_generation(&mut self) {
+        self.generation_started = true;
+        self.generation_finished = true;
+        self.has_moved_player = true;
+    }
+
+    /// None while the assets are still loading, else the part of the map that is generated
+    pub fn generation_progress(&self, map_data: &MapData) -> Option<f32> {
+        if !self.generation_started {
+            return None;
+        }
+        let tiles = map_data.tiles();
+        let generated = tiles.iter().filter(|tile| tile.is_some()).count();
+        Some(if tiles.data.is_empty() { 0.0 } else { generated as f32 / tiles.data.len() as f32 })
+    }
 }
 
 fn start_generation(
-    pyxel_file: &PyxelFile,
+    map: &dyn MapSource,
+    map_config: &MapConfig,
     tile_assets: &mut TileAssets,
+    tile_metrics: &TileMetrics,
     texture_atlases: &mut Assets<TextureAtlas>,
     map_data: &mut MapData,
 ) {
-    for layer in pyxel_file.layers.iter() {
-        println!("layer {:?}: {:?}", layer.number, layer.name);
-    }
-
-    let base_layer = pyxel_file.layers.iter().find(|layer| layer.number == 2).unwrap();
-    let entity_layer = pyxel_file.layers.iter().find(|layer| layer.number == 1).unwrap();
+    let fixed = map_config.fixed;
+    let seed = map_config.seed;
+    let base_tiles = map.base_tiles();
 
-    let min_tile = base_layer.tiles.iter()
-        .filter(|tile| tile.tile != -1)
+    let min_tile = base_tiles.iter()
         .map(|tile| (tile.x, tile.y))
         .fold((i32::MAX, i32::MAX), |(min_x, min_y), (x, y)| (min(min_x, x), min(min_y, y)));
-    let max_tile = base_layer.tiles.iter()
-        .filter(|tile| tile.tile != -1)
+    let max_tile = base_tiles.iter()
         .map(|tile| (tile.x, tile.y))
         .fold((i32::MIN, i32::MIN), |(max_x, max_y), (x, y)| (max(max_x, x), max(max_y, y)));
-    println!("min_tile: {:?}, max_tile: {:?}", min_tile, max_tile);
+    debug!("min_tile: {:?}, max_tile: {:?}", min_tile, max_tile);
+    let to_map_pos = |x: i32, y: i32| ((x - min_tile.0) as usize, (max_tile.1 - y) as usize); // editors count y downwards
 
     let mut tiles = MultiVec::new(-1, (max_tile.0 - min_tile.0 + 1) as usize, (max_tile.1 - min_tile.1 + 1) as usize);
-    for tile in base_layer.tiles.iter() {
-        if tile.tile != -1 {
-            let x = (tile.x - min_tile.0) as usize;
-            let y = (tile.y - min_tile.1) as usize;
-            let flipped_y = (max_tile.1 - min_tile.1) as usize - y;
-            *(tiles.get_mut(x, flipped_y).unwrap()) = tile.tile;
-        }
+    for tile in base_tiles.iter() {
+        let (x, flipped_y) = to_map_pos(tile.x, tile.y);
+        *(tiles.get_mut(x, flipped_y).unwrap()) = tile.tile.to_packed(); // rotated tiles train as tiles of their own
     }
 
     // for each entity tile type, spawn on base layer tiles
-    tile_assets.spawn_entities_for_base_tile = HashMap::<i32, HashSet<i32>>::new();
-    for entity_tile in entity_layer.tiles.iter().filter(|tile| tile.tile != -1) {
-        let base_tile = base_layer.tiles.iter()
-            .find(|tile| tile.x == entity_tile.x && tile.y == entity_tile.y).unwrap();
-        tile_assets.spawn_entities_for_base_tile.entry(base_tile.tile).or_insert(default()).insert(entity_tile.tile);
+    let base_tile_at = base_tiles.iter()
+        .map(|tile| ((tile.x, tile.y), tile.tile.tile_id))
+        .collect::<HashMap<_, _>>();
+    tile_assets.spawn_entities_for_base_tile = HashMap::<i32, BTreeSet<i32>>::new();
+    tile_assets.fixed_objects = fixed.then(HashMap::new);
+    for map_object in map.objects() {
+        let Some(base_tile) = base_tile_at.get(&(map_object.x, map_object.y)) else {
+            warn!("object {} at ({},{}) is not on a base tile", map_object.object.tile_id, map_object.x, map_object.y);
+            continue;
+        };
+        tile_assets.spawn_entities_for_base_tile.entry(*base_tile).or_insert(default()).insert(map_object.object.tile_id);
+        if let Some(fixed_objects) = tile_assets.fixed_objects.as_mut() {
+            fixed_objects.insert(to_map_pos(map_object.x, map_object.y), map_object.object.tile_id);
+        }
     }
 
     let (tx, rx) = std::sync::mpsc::channel();
     tile_assets.rx = Some(Mutex::new(rx));
 
-    let map_size = 64;
+    let map_size = if fixed { (tiles.w, tiles.h) } else { (map_config.size, map_config.size) };
 
     std::thread::spawn(move || {
         
-        let generator = WaveFunctionCollapseGenerator::new(
-            tiles,
-            map_size,
-            2,
-            666
-        );
+        let generator: Box<dyn Iterator<Item = (usize, usize, i32)>> = if fixed {
+            let w = tiles.w;
+            Box::new(tiles.data.into_iter().enumerate()
+                .filter(|(_, packed_tile)| *packed_tile != -1)
+                .map(move |(i, packed_tile)| (i % w, i / w, packed_tile)))
+        } else {
+            Box::new(WaveFunctionCollapseGenerator::new(
+                tiles,
+                map_size.0,
+                2,
+                seed
+            ))
+        };
 
         #[cfg(debug_assertions)]
-        let mut map = MultiVec::new(-1, map_size, map_size);
+        let mut map = MultiVec::new(-1, map_size.0, map_size.1);
 
-        for (x, y, tile_id) in generator {
-            tx.send((x, y, tile_id)).unwrap();
+        for (x, y, packed_tile) in generator {
+            if tx.send((x, y, packed_tile)).is_err() {
+                return; // world was cleared, nobody waits for these tiles anymore
+            }
 
             #[cfg(debug_assertions)]
             {
-                *map.get_mut(x, y).unwrap() = tile_id;
+                *map.get_mut(x, y).unwrap() = GameTile::from_packed(packed_tile).tile_id;
             }
         }
         
@@ -262,68 +504,158 @@ fn start_generation(
         }
     });
 
+    tile_assets.tileset = map.tileset();
     let texture_atlas = TextureAtlas::from_grid(
         tile_assets.tileset.clone(),
-        Vec2::new(pyxel_file.tilewidth as f32, pyxel_file.tileheight as f32),
-        8, 8, None, None);
+        tile_metrics.tile_size,
+        tile_metrics.atlas_columns, tile_metrics.atlas_rows, None, None);
     let texture_atlas_handle = texture_atlases.add(texture_atlas);
     tile_assets.texture_atlas = texture_atlas_handle;
 
-    *map_data = MapData { 0: MultiVec::new(None, map_size, map_size) };
+    *map_data = MapData::new(map_size.0, map_size.1);
 }
 
 fn spawn_generated(
-    commands: &mut Commands,
-    tile_assets: &mut TileAssets,
-    player_transform: &mut Transform,
-    map_data: &mut MapData,
+    mut commands: Commands,
+    mut tile_assets: ResMut<TileAssets>,
+    tile_metrics: Res<TileMetrics>,
+    map_config: Res<MapConfig>,
+    mut map_data: ResMut<MapData>,
 ) {
     if tile_assets.rx.is_none() {
         return;
     }
 
+    let tile_assets = &mut *tile_assets;
     let rx = tile_assets.rx.as_ref().unwrap().lock().unwrap();
     let max_tiles_per_frame = 32;
     for _ in 0..max_tiles_per_frame {
         let next = rx.try_recv();
+        if next == Err(mpsc::TryRecvError::Disconnected) {
+            tile_assets.generation_finished = true;
+        }
         if next.is_err() { break; }
-        let (x, y, tile_id) = next.unwrap();
+        let (x, y, packed_tile) = next.unwrap();
         
-        debug!("rx received: ({},{}) = {}", x, y, tile_id);
+        debug!("rx received: ({},{}) = {}", x, y, packed_tile);
         
-        if tile_id == -1 {
+        if packed_tile == -1 {
             warn!("rx received tile_id == -1");
             return;
         }
 
-        let base_entity = commands.spawn((
-            create_bundle_for_tile(x, y, tile_id, -1.0, &*tile_assets),
-            GameTile { tile_id },
-            Name::new(format!("Tile {tile_id} ({x},{y})")),
-        )).id();
-
-        let map_data_cell = map_data.0.get_mut(x, y).expect("accessing map data failed");
-        assert!(map_data_cell.is_none(), "map data cell should be None");
-        *map_data_cell = Some(base_entity);
+        let tile = GameTile::from_packed(packed_tile);
+        let tile_id = tile.tile_id;
+        assert!(map_data.get(x, y).is_none(), "map data cell should be None");
+        map_data.set(x, y, tile);
 
         // Generate entities on correct base tiles
         let spawn_rate = 0.2 as f32;
 
-        if let Some(entities) = tile_assets.spawn_entities_for_base_tile.get(&tile_id) {
-            if rand::random::<f32>() < spawn_rate {
-                let entity = entities.iter().choose(&mut rand::thread_rng()).expect("should have at least one entity");
+        if let Some(fixed_objects) = tile_assets.fixed_objects.as_ref() {
+            if let Some(entity) = fixed_objects.get(&(x, y)) {
+                let object = GameObject { tile_id: *entity };
+                commands.spawn((
+                    create_bundle_for_tile(TilePos::from_index(x, y), *entity, default(), object.z(), &*tile_assets, &tile_metrics),
+                    TilePos::from_index(x, y),
+                    object,
+                    Name::new(format!("Object {entity} ({x},{y})")),
+                ));
+            }
+        } else if let Some(entities) = tile_assets.spawn_entities_for_base_tile.get(&tile_id) {
+            let mut rng = object_rng(map_config.seed, x, y);
+            if rng.gen::<f32>() < spawn_rate {
+                let entity = entities.iter().choose(&mut rng).expect("should have at least one entity");
+                let object = GameObject { tile_id: *entity };
                 commands.spawn((
-                    create_bundle_for_tile(x, y, *entity, -0.5, &*tile_assets),
-                    GameObject { tile_id: *entity },
+                    create_bundle_for_tile(TilePos::from_index(x, y), *entity, default(), object.z(), &*tile_assets, &tile_metrics),
+                    TilePos::from_index(x, y),
+                    object,
                     Name::new(format!("Object {entity} ({x},{y})")),
                 ));
             }
         }
+    }
+}
+
+/// Random numbers for the objects on cell `(x, y)`, the same for a seed whatever order the cells are generated in
+fn object_rng(seed: u64, x: usize, y: usize) -> StdRng {
+    StdRng::seed_from_u64(seed ^ ((x as u64) << 32 | y as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+/// Objects that were spawned or moved to another cell
+type MovedObjectFilter = (With<GameObject>, Changed<TilePos>);
+
+/// Keeps the object occupancy of `MapData` in sync with spawned, moved and despawned objects
+fn track_objects(
+    mut map_data: ResMut<MapData>,
+    moved: Query<(Entity, &TilePos), MovedObjectFilter>,
+    mut removed: RemovedComponents<GameObject>,
+) {
+    for (entity, pos) in &moved {
+        map_data.place_object(entity, *pos);
+    }
+    for entity in removed.read() {
+        map_data.remove_object(entity);
+    }
+}
 
-        let player_spawn_tile = 9;
-        if !tile_assets.has_moved_player && tile_id == player_spawn_tile {
-            player_transform.translation = Vec3::new(x as f32, y as f32, player_transform.translation.z);
-            tile_assets.has_moved_player = true;
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::{crafting::Buildable, items::{Inventory, InventoryChanged, ItemId, ItemRegistry}};
+
+    fn empty_tile_assets() -> TileAssets {
+        TileAssets {
+            map: MapHandle::Pyxel(default()),
+            tileset_file: default(),
+            tileset: default(),
+            generation_started: true,
+            generation_finished: true,
+            has_moved_player: true,
+            rx: None,
+            texture_atlas: default(),
+            spawn_entities_for_base_tile: HashMap::new(),
+            fixed_objects: None,
         }
     }
+
+    #[test]
+    fn regenerating_refunds_pending_builds() {
+        let mut app = App::new();
+        app.add_state::<AppState>();
+        app.add_event::<RegenerateWorld>();
+        app.add_event::<InventoryChanged>();
+        app.init_resource::<MapConfig>();
+        app.init_resource::<MapData>();
+        app.init_resource::<ItemRegistry>();
+        app.insert_resource(empty_tile_assets());
+        app.add_systems(Update, regenerate_on_request);
+
+        let mut player = Player::new();
+        player.inventory = Inventory::from_iter([("wood", 7)]);
+        app.world.spawn((player, Transform::default()));
+        let price = Inventory::from_iter([("wood", 5)]);
+        let reserved = price.clone();
+        app.world.run_system_once(move |mut inventory: PlayerInventory| {
+            inventory.try_remove(&reserved, ChangeReason::Reserved).unwrap();
+        });
+        let build = app.world.spawn((BuildProgress {
+            others: vec![],
+            price_inv: price,
+            start_time: 0.0,
+            time_to_build: 5.0,
+            buildable: Buildable::Campfire,
+        }, ProgressBar)).id();
+
+        let wood = ItemId::from("wood");
+        let count = |app: &mut App| app.world.query::<&Player>().single(&app.world).inventory.count(&wood);
+        assert_eq!(count(&mut app), 2);
+        app.world.send_event(RegenerateWorld { seed: 1, size: 16 });
+        app.update();
+        assert_eq!(count(&mut app), 7);
+        assert!(app.world.get_entity(build).is_none());
+    }
 }
@@ -0,0 +1,716 @@
+use std::{collections::HashMap, str::FromStr};
+
+use bevy::{
+    prelude::*,
+    asset::{io::Reader, AssetLoader, AssetPath, AsyncReadExt, BoxedFuture, LoadContext, ParseAssetPathError, ReadAssetBytesError},
+};
+use derive_more::{Display, From};
+use serde::Deserialize;
+
+use crate::{
+    game_object::{GameObject, ObjectType},
+    game_tile::{GameTile, TileOrientation},
+    map_source::{MapObject, MapProperty, MapSource, MapTile, Properties},
+};
+
+/// Map made with Tiled, loaded from `.tmx` (XML) or `.tmj` (JSON).
+/// Only finite maps with csv / array layer data are supported, tiles come from the first tileset.
+/// Objects are always drawn unflipped, only the terrain keeps the orientation of its tiles.
+#[derive(Asset, TypePath)]
+pub struct TiledMap {
+    pub width: i32,      // in tiles
+    pub tilewidth: i32,  // in pixels
+    pub tileheight: i32, // in pixels
+    pub layers: Vec<TiledLayer>, // bottom to top, groups flattened
+    pub tileset: Handle<Image>,
+    pub tileset_columns: i32, // number of tiles in the tileset image in x direction
+    pub tileset_rows: i32,
+    pub tile_properties: HashMap<i32, Properties>,
+}
+
+pub enum TiledLayer {
+    Tiles(Vec<Option<GameTile>>), // row by row, `width` tiles per row
+    Objects(Vec<TiledObject>),
+}
+
+pub struct TiledObject {
+    pub name: String,
+    pub class: String,
+    pub tile: Option<GameTile>, // set for tile objects
+    pub center: Vec2, // in pixels, y pointing down
+}
+
+impl TiledObject {
+    /// Tile objects use their tile, other objects are matched by class or name, e.g. "Tree"
+    pub fn game_object(&self) -> Option<GameObject> {
+        if let Some(tile) = self.tile {
+            return Some(GameObject { tile_id: tile.tile_id });
+        }
+        self.class.parse::<ObjectType>()
+            .or_else(|_| self.name.parse::<ObjectType>())
+            .ok()
+            .map(GameObject::from)
+    }
+}
+
+impl MapSource for TiledMap {
+    fn tile_size(&self) -> Vec2 {
+        Vec2::new(self.tilewidth as f32, self.tileheight as f32)
+    }
+
+    fn tileset(&self) -> Handle<Image> {
+        self.tileset.clone()
+    }
+
+    fn atlas_grid(&self) -> (usize, usize) {
+        (self.tileset_columns as usize, self.tileset_rows as usize)
+    }
+
+    /// the bottom tile layer is the terrain
+    fn base_tiles(&self) -> Vec<MapTile> {
+        self.tile_layers().next()
+            .map(|tiles| self.placed_tiles(tiles).map(|(x, y, tile)| MapTile { x, y, tile }).collect())
+            .unwrap_or_default()
+    }
+
+    /// tiles on the other tile layers and everything on object layers
+    fn objects(&self) -> Vec<MapObject> {
+        let from_tile_layers = self.tile_layers().skip(1)
+            .flat_map(|tiles| self.placed_tiles(tiles))
+            .map(|(x, y, tile)| (x, y, tile.orientation, GameObject { tile_id: tile.tile_id }));
+
+        let from_object_layers = self.layers.iter()
+            .filter_map(|layer| match layer {
+                TiledLayer::Objects(objects) => Some(objects),
+                TiledLayer::Tiles(_) => None,
+            })
+            .flatten()
+            .filter_map(|object| Some((
+                (object.center.x / self.tilewidth as f32).floor() as i32,
+                (object.center.y / self.tileheight as f32).floor() as i32,
+                object.tile.map(|tile| tile.orientation).unwrap_or_default(),
+                object.game_object()?,
+            )));
+
+        let mut flipped = 0;
+        let objects = from_tile_layers.chain(from_object_layers)
+            .map(|(x, y, orientation, object)| {
+                if orientation != TileOrientation::default() {
+                    flipped += 1;
+                }
+                MapObject { x, y, object }
+            })
+            .collect();
+        if flipped > 0 {
+            warn!("{flipped} objects in the Tiled map are flipped or rotated, objects are drawn unflipped");
+        }
+        objects
+    }
+
+    fn tile_properties(&self, tile_id: i32) -> Option<&Properties> {
+        self.tile_properties.get(&tile_id)
+    }
+}
+
+impl TiledMap {
+    fn tile_layers(&self) -> impl Iterator<Item = &Vec<Option<GameTile>>> {
+        self.layers.iter().filter_map(|layer| match layer {
+            TiledLayer::Tiles(tiles) => Some(tiles),
+            TiledLayer::Objects(_) => None,
+        })
+    }
+
+    fn placed_tiles<'a>(&self, tiles: &'a [Option<GameTile>]) -> impl Iterator<Item = (i32, i32, GameTile)> + 'a {
+        let width = self.width;
+        tiles.iter().enumerate()
+            .filter_map(move |(i, tile)| Some((i as i32 % width, i as i32 / width, (*tile)?)))
+    }
+}
+
+#[derive(Debug, Display, From)]
+pub enum TiledLoaderError {
+    #[display(fmt = "could not read Tiled file: {}", _0)]
+    Io(std::io::Error),
+    #[display(fmt = "could not read Tiled tileset: {}", _0)]
+    ReadAsset(ReadAssetBytesError),
+    #[display(fmt = "invalid path in Tiled file: {}", _0)]
+    Path(ParseAssetPathError),
+    #[display(fmt = "could not parse Tiled JSON: {}", _0)]
+    Json(serde_json::Error),
+    #[display(fmt = "could not parse Tiled XML: {}", _0)]
+    Xml(roxmltree::Error),
+    #[display(fmt = "unsupported Tiled file: {}", _0)]
+    #[from(ignore)]
+    Unsupported(String),
+}
+
+impl std::error::Error for TiledLoaderError {}
+
+#[derive(Default)]
+pub struct TiledLoader;
+
+impl AssetLoader for TiledLoader {
+    type Asset = TiledMap;
+    type Settings = ();
+    type Error = TiledLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<TiledMap, TiledLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            let map_path = load_context.asset_path().clone();
+            let mut map = if is_xml(&map_path) { parse_tmx(&bytes)? } else { parse_tmj(&bytes)? };
+
+            // the game renders a single texture atlas, so only the first tileset is used
+            let mut tileset_refs = std::mem::take(&mut map.tilesets);
+            tileset_refs.sort_by_key(|tileset| tileset.firstgid);
+            let first_tileset = tileset_refs.first()
+                .ok_or_else(|| TiledLoaderError::Unsupported("map without tileset".to_string()))?;
+            let firstgid = first_tileset.firstgid;
+            let last_gid = tileset_refs.get(1).map(|tileset| tileset.firstgid - 1).unwrap_or(u32::MAX);
+
+            let (tileset, tileset_path) = match &first_tileset.source {
+                Some(source) => {
+                    let tileset_path = map_path.resolve_embed(source)?;
+                    let tileset_bytes = load_context.read_asset_bytes(&tileset_path).await?;
+                    let tileset = if is_xml(&tileset_path) { parse_tsx(&tileset_bytes)? } else { parse_tsj(&tileset_bytes)? };
+                    (tileset, tileset_path)
+                }
+                None => (first_tileset.tileset.clone(), map_path.clone()),
+            };
+            let image = tileset.image.clone()
+                .ok_or_else(|| TiledLoaderError::Unsupported("tileset without a single tileset image".to_string()))?;
+            let image = load_context.load(tileset_path.resolve_embed(&image)?);
+            build_map(map, tileset, firstgid, last_gid, image)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmx", "tmj"]
+    }
+}
+
+/// Decodes the layers of a parsed map, tiles from `firstgid` to `last_gid` belong to `tileset`
+fn build_map(map: RawMap, tileset: RawTileset, firstgid: u32, last_gid: u32, image: Handle<Image>) -> Result<TiledMap, TiledLoaderError> {
+    if tileset.columns <= 0 {
+        return Err(TiledLoaderError::Unsupported("tileset without columns, save it with a newer Tiled".to_string()));
+    }
+
+    let decode = |gid: u32| decode_gid(gid, firstgid, last_gid);
+    let layers = map.layers.into_iter()
+        .map(|layer| match layer {
+            RawLayer::Tiles(gids) => {
+                if gids.len() != (map.width * map.height) as usize {
+                    return Err(TiledLoaderError::Unsupported("tile layer size differs from map size".to_string()));
+                }
+                Ok(TiledLayer::Tiles(gids.into_iter().map(decode).collect()))
+            }
+            RawLayer::Objects(objects) => Ok(TiledLayer::Objects(objects.into_iter()
+                .map(|object| {
+                    let tile = object.gid.and_then(decode);
+                    // tile objects are anchored bottom left, everything else top left
+                    let top = if object.gid.is_some() { object.y - object.height } else { object.y };
+                    TiledObject {
+                        name: object.name,
+                        class: object.class,
+                        tile,
+                        center: Vec2::new(object.x + object.width / 2.0, top + object.height / 2.0),
+                    }
+                })
+                .collect())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(TiledMap {
+        width: map.width,
+        tilewidth: map.tilewidth,
+        tileheight: map.tileheight,
+        layers,
+        tileset: image,
+        tileset_columns: tileset.columns,
+        tileset_rows: (tileset.tilecount + tileset.columns - 1) / tileset.columns,
+        tile_properties: tileset.tiles.into_iter().collect(),
+    })
+}
+
+fn is_xml(path: &AssetPath) -> bool {
+    path.get_full_extension().is_some_and(|extension| extension == "tmx" || extension == "tsx")
+}
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const GID_MASK: u32 = 0x0fff_ffff; // the remaining flag is only used by hexagonal maps
+
+/// Tiled stores the tile orientation in the upper bits of the global tile id
+fn decode_gid(gid: u32, firstgid: u32, last_gid: u32) -> Option<GameTile> {
+    let id = gid & GID_MASK;
+    if id == 0 {
+        return None;
+    }
+    if id < firstgid || id > last_gid {
+        warn!("Tiled tile {} is not part of the first tileset, skipping it", id);
+        return None;
+    }
+
+    // Tiled flips diagonally first, then horizontally, then vertically
+    let flags = (gid & FLIPPED_DIAGONALLY != 0, gid & FLIPPED_HORIZONTALLY != 0, gid & FLIPPED_VERTICALLY != 0);
+    let (flip_x, rot) = match flags {
+        (false, false, false) => (false, 0),
+        (false, true,  false) => (true,  0),
+        (false, false, true ) => (true,  2),
+        (false, true,  true ) => (false, 2),
+        (true,  false, false) => (true,  3),
+        (true,  true,  false) => (false, 1),
+        (true,  false, true ) => (false, 3),
+        (true,  true,  true ) => (true,  1),
+    };
+
+    Some(GameTile { tile_id: (id - firstgid) as i32, orientation: TileOrientation { flip_x, rot } })
+}
+
+// format independent representation, filled from either .tmx or .tmj
+
+struct RawMap {
+    width: i32,
+    height: i32,
+    tilewidth: i32,
+    tileheight: i32,
+    layers: Vec<RawLayer>,
+    tilesets: Vec<RawTilesetRef>,
+}
+
+/// checked before the layers are parsed, infinite maps store their tiles in chunks
+fn infinite_map_error() -> TiledLoaderError {
+    TiledLoaderError::Unsupported("infinite maps".to_string())
+}
+
+enum RawLayer {
+    Tiles(Vec<u32>),
+    Objects(Vec<RawObject>),
+}
+
+struct RawObject {
+    name: String,
+    class: String,
+    gid: Option<u32>,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+struct RawTilesetRef {
+    firstgid: u32,
+    source: Option<String>, // external .tsx / .tsj, relative to the map
+    tileset: RawTileset,    // embedded tileset
+}
+
+#[derive(Clone, Default)]
+struct RawTileset {
+    image: Option<String>,
+    columns: i32,
+    tilecount: i32,
+    tiles: Vec<(i32, Properties)>,
+}
+
+fn property(property_type: &str, value: &str) -> Option<MapProperty> {
+    match property_type {
+        "bool" => Some(MapProperty::Bool(value == "true")),
+        "int" | "object" => value.parse().ok().map(MapProperty::Int),
+        "float" => value.parse().ok().map(MapProperty::Float),
+        "class" => None, // nested properties are not supported
+        _ => Some(MapProperty::String(value.to_string())), // string, color, file
+    }
+}
+
+// .tmj / .tsj
+
+#[derive(Deserialize)]
+struct TmjHeader {
+    #[serde(default)]
+    infinite: bool,
+}
+
+#[derive(Deserialize)]
+struct TmjMap {
+    width: i32,
+    height: i32,
+    tilewidth: i32,
+    tileheight: i32,
+    layers: Vec<TmjLayer>,
+    tilesets: Vec<TmjTileset>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TmjLayer {
+    TileLayer { data: TmjData },
+    ObjectGroup { objects: Vec<TmjObject> },
+    Group { layers: Vec<TmjLayer> },
+    ImageLayer {},
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TmjData {
+    Array(Vec<u32>),
+    Encoded(serde::de::IgnoredAny),
+}
+
+#[derive(Deserialize)]
+struct TmjObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, alias = "class")]
+    r#type: String,
+    gid: Option<u32>,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+}
+
+#[derive(Deserialize)]
+struct TmjProperty {
+    name: String,
+    #[serde(default)]
+    r#type: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct TmjTileset {
+    #[serde(default)]
+    firstgid: u32,
+    source: Option<String>,
+    image: Option<String>,
+    #[serde(default)]
+    columns: i32,
+    #[serde(default)]
+    tilecount: i32,
+    #[serde(default)]
+    tiles: Vec<TmjTile>,
+}
+
+#[derive(Deserialize)]
+struct TmjTile {
+    id: i32,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+}
+
+fn tmj_properties(properties: Vec<TmjProperty>) -> Properties {
+    properties.into_iter()
+        .filter_map(|property| {
+            let value = match property.value {
+                serde_json::Value::String(value) => value,
+                value => value.to_string(),
+            };
+            Some((property.name, self::property(&property.r#type, &value)?))
+        })
+        .collect()
+}
+
+fn tmj_layers(layers: Vec<TmjLayer>, out: &mut Vec<RawLayer>) -> Result<(), TiledLoaderError> {
+    for layer in layers {
+        match layer {
+            TmjLayer::TileLayer { data: TmjData::Array(gids) } => out.push(RawLayer::Tiles(gids)),
+            TmjLayer::TileLayer { data: TmjData::Encoded(_) } => {
+                return Err(TiledLoaderError::Unsupported("encoded layer data, save with CSV layer format".to_string()));
+            }
+            TmjLayer::ObjectGroup { objects } => out.push(RawLayer::Objects(objects.into_iter()
+                .map(|object| RawObject {
+                    name: object.name,
+                    class: object.r#type,
+                    gid: object.gid,
+                    x: object.x,
+                    y: object.y,
+                    width: object.width,
+                    height: object.height,
+                })
+                .collect())),
+            TmjLayer::Group { layers } => tmj_layers(layers, out)?,
+            TmjLayer::ImageLayer {} => {}
+        }
+    }
+    Ok(())
+}
+
+fn tmj_tileset(tileset: TmjTileset) -> RawTilesetRef {
+    RawTilesetRef {
+        firstgid: tileset.firstgid,
+        source: tileset.source,
+        tileset: RawTileset {
+            image: tileset.image,
+            columns: tileset.columns,
+            tilecount: tileset.tilecount,
+            tiles: tileset.tiles.into_iter().map(|tile| (tile.id, tmj_properties(tile.properties))).collect(),
+        },
+    }
+}
+
+fn parse_tmj(bytes: &[u8]) -> Result<RawMap, TiledLoaderError> {
+    let header: TmjHeader = serde_json::from_slice(bytes)?;
+    if header.infinite {
+        return Err(infinite_map_error());
+    }
+    let map: TmjMap = serde_json::from_slice(bytes)?;
+    let mut layers = Vec::new();
+    tmj_layers(map.layers, &mut layers)?;
+    Ok(RawMap {
+        width: map.width,
+        height: map.height,
+        tilewidth: map.tilewidth,
+        tileheight: map.tileheight,
+        layers,
+        tilesets: map.tilesets.into_iter().map(tmj_tileset).collect(),
+    })
+}
+
+fn parse_tsj(bytes: &[u8]) -> Result<RawTileset, TiledLoaderError> {
+    Ok(tmj_tileset(serde_json::from_slice(bytes)?).tileset)
+}
+
+// .tmx / .tsx
+
+fn xml_text(bytes: &[u8]) -> Result<&str, TiledLoaderError> {
+    std::str::from_utf8(bytes).map_err(|_| TiledLoaderError::Unsupported("file is not UTF-8".to_string()))
+}
+
+fn attribute<T: FromStr>(node: roxmltree::Node, name: &str) -> Result<T, TiledLoaderError> {
+    node.attribute(name)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| TiledLoaderError::Unsupported(format!("missing or invalid attribute {} on <{}>", name, node.tag_name().name())))
+}
+
+fn attribute_or<T: FromStr>(node: roxmltree::Node, name: &str, default: T) -> Result<T, TiledLoaderError> {
+    if node.has_attribute(name) { attribute(node, name) } else { Ok(default) }
+}
+
+fn children<'a, 'input>(node: roxmltree::Node<'a, 'input>, tag: &'a str) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children().filter(move |child| child.has_tag_name(tag))
+}
+
+fn tmx_properties(node: roxmltree::Node) -> Properties {
+    children(node, "properties")
+        .flat_map(|properties| children(properties, "property"))
+        .filter_map(|property_node| {
+            let name = property_node.attribute("name")?;
+            let value = property_node.attribute("value").or(property_node.text()).unwrap_or_default();
+            let property_type = property_node.attribute("type").unwrap_or("string");
+            Some((name.to_string(), property(property_type, value)?))
+        })
+        .collect()
+}
+
+fn tmx_layers(node: roxmltree::Node, out: &mut Vec<RawLayer>) -> Result<(), TiledLoaderError> {
+    for child in node.children() {
+        match child.tag_name().name() {
+            "layer" => {
+                let data = children(child, "data").next()
+                    .ok_or_else(|| TiledLoaderError::Unsupported("layer without data".to_string()))?;
+                let gids = match data.attribute("encoding") {
+                    Some("csv") => data.text().unwrap_or_default()
+                        .split(',')
+                        .map(|gid| gid.trim().parse::<u32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| TiledLoaderError::Unsupported("invalid csv layer data".to_string()))?,
+                    None => children(data, "tile")
+                        .map(|tile| attribute_or(tile, "gid", 0))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    Some(_) => return Err(TiledLoaderError::Unsupported("encoded layer data, save with CSV layer format".to_string())),
+                };
+                out.push(RawLayer::Tiles(gids));
+            }
+            "objectgroup" => {
+                let objects = children(child, "object")
+                    .map(|object| Ok(RawObject {
+                        name: object.attribute("name").unwrap_or_default().to_string(),
+                        // "type" was renamed to "class" in Tiled 1.9
+                        class: object.attribute("class").or(object.attribute("type")).unwrap_or_default().to_string(),
+                        gid: object.attribute("gid").map(|_| attribute(object, "gid")).transpose()?,
+                        x: attribute(object, "x")?,
+                        y: attribute(object, "y")?,
+                        width: attribute_or(object, "width", 0.0)?,
+                        height: attribute_or(object, "height", 0.0)?,
+                    }))
+                    .collect::<Result<Vec<_>, TiledLoaderError>>()?;
+                out.push(RawLayer::Objects(objects));
+            }
+            "group" => tmx_layers(child, out)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn tmx_tileset(node: roxmltree::Node) -> RawTileset {
+    RawTileset {
+        image: children(node, "image").next().and_then(|image| image.attribute("source")).map(str::to_string),
+        columns: attribute_or(node, "columns", 0).unwrap_or(0),
+        tilecount: attribute_or(node, "tilecount", 0).unwrap_or(0),
+        tiles: children(node, "tile")
+            .filter_map(|tile| Some((attribute(tile, "id").ok()?, tmx_properties(tile))))
+            .collect(),
+    }
+}
+
+fn parse_tmx(bytes: &[u8]) -> Result<RawMap, TiledLoaderError> {
+    let document = roxmltree::Document::parse(xml_text(bytes)?)?;
+    let map = document.root_element();
+    if attribute_or(map, "infinite", 0)? != 0 {
+        return Err(infinite_map_error());
+    }
+    let mut layers = Vec::new();
+    tmx_layers(map, &mut layers)?;
+    Ok(RawMap {
+        width: attribute(map, "width")?,
+        height: attribute(map, "height")?,
+        tilewidth: attribute(map, "tilewidth")?,
+        tileheight: attribute(map, "tileheight")?,
+        layers,
+        tilesets: children(map, "tileset")
+            .map(|tileset| Ok(RawTilesetRef {
+                firstgid: attribute(tileset, "firstgid")?,
+                source: tileset.attribute("source").map(str::to_string),
+                tileset: tmx_tileset(tileset),
+            }))
+            .collect::<Result<Vec<_>, TiledLoaderError>>()?,
+    })
+}
+
+fn parse_tsx(bytes: &[u8]) -> Result<RawTileset, TiledLoaderError> {
+    let document = roxmltree::Document::parse(xml_text(bytes)?)?;
+    Ok(tmx_tileset(document.root_element()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H: u32 = FLIPPED_HORIZONTALLY;
+    const V: u32 = FLIPPED_VERTICALLY;
+    const D: u32 = FLIPPED_DIAGONALLY;
+
+    fn orientation(flip_x: bool, rot: u8) -> TileOrientation {
+        TileOrientation { flip_x, rot }
+    }
+
+    #[test]
+    fn decode_gid_flags() {
+        let cases = [
+            (0,         orientation(false, 0)),
+            (H,         orientation(true,  0)),
+            (V,         orientation(true,  2)),
+            (H | V,     orientation(false, 2)),
+            (D,         orientation(true,  3)),
+            (D | H,     orientation(false, 1)),
+            (D | V,     orientation(false, 3)),
+            (D | H | V, orientation(true,  1)),
+        ];
+        for (flags, expected) in cases {
+            let tile = decode_gid(flags | 7, 5, 20).unwrap();
+            assert_eq!(tile.tile_id, 2, "flags {flags:#x}");
+            assert_eq!(tile.orientation, expected, "flags {flags:#x}");
+        }
+        assert!(decode_gid(0, 1, 20).is_none());
+        assert!(decode_gid(H, 1, 20).is_none());
+        assert!(decode_gid(4, 5, 20).is_none(), "before the first tileset");
+        assert!(decode_gid(21, 5, 20).is_none(), "in the next tileset");
+    }
+
+    // 3x2 map: terrain, a group with a flipped tree tile and an object layer with a tile object and a "Stone" object
+    const TMJ: &str = r#"{
+        "width": 3, "height": 2, "tilewidth": 16, "tileheight": 16, "infinite": false,
+        "layers": [
+            { "type": "tilelayer", "name": "ground", "data": [1, 2, 3, 4, 5, 6] },
+            { "type": "group", "name": "decoration", "layers": [
+                { "type": "tilelayer", "name": "trees", "data": [0, 0, 2147483661, 0, 0, 0] },
+                { "type": "objectgroup", "name": "things", "objects": [
+                    { "id": 1, "gid": 14, "x": 16, "y": 32, "width": 16, "height": 16 },
+                    { "id": 2, "name": "rock", "type": "Stone", "x": 0, "y": 0, "width": 16, "height": 16 }
+                ] }
+            ] }
+        ],
+        "tilesets": [{
+            "firstgid": 1, "image": "tiles.png", "columns": 10, "tilecount": 30,
+            "tiles": [{ "id": 2, "properties": [{ "name": "walkable", "type": "bool", "value": false }] }]
+        }]
+    }"#;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <map version="1.10" orientation="orthogonal" width="3" height="2" tilewidth="16" tileheight="16" infinite="0">
+            <tileset firstgid="1" name="tiles" tilewidth="16" tileheight="16" tilecount="30" columns="10">
+                <image source="tiles.png" width="160" height="48"/>
+                <tile id="2">
+                    <properties><property name="walkable" type="bool" value="false"/></properties>
+                </tile>
+            </tileset>
+            <layer id="1" name="ground" width="3" height="2">
+                <data encoding="csv">
+                1,2,3,
+                4,5,6
+                </data>
+            </layer>
+            <group id="2" name="decoration">
+                <layer id="3" name="trees" width="3" height="2">
+                    <data encoding="csv">0,0,2147483661,0,0,0</data>
+                </layer>
+                <objectgroup id="4" name="things">
+                    <object id="1" gid="14" x="16" y="32" width="16" height="16"/>
+                    <object id="2" name="rock" class="Stone" x="0" y="0" width="16" height="16"/>
+                </objectgroup>
+            </group>
+        </map>"#;
+
+    fn build(map: RawMap) -> TiledMap {
+        let tileset = map.tilesets[0].tileset.clone();
+        build_map(map, tileset, 1, u32::MAX, default()).unwrap()
+    }
+
+    fn check_fixture(map: TiledMap) {
+        assert_eq!(map.atlas_grid(), (10, 3));
+        let base = map.base_tiles().iter().map(|tile| (tile.x, tile.y, tile.tile.tile_id)).collect::<Vec<_>>();
+        assert_eq!(base, [(0, 0, 0), (1, 0, 1), (2, 0, 2), (0, 1, 3), (1, 1, 4), (2, 1, 5)]);
+
+        let objects = map.objects().iter().map(|object| (object.x, object.y, object.object.tile_id)).collect::<Vec<_>>();
+        assert_eq!(objects, [(2, 0, 12), (1, 1, 13), (0, 0, GameObject::from(ObjectType::Stone).tile_id)]);
+        let TiledLayer::Tiles(trees) = &map.layers[1] else { panic!("the group's tile layer should come second") };
+        assert_eq!(trees[2].unwrap().orientation, orientation(true, 0));
+
+        assert_eq!(map.tile_properties(2).and_then(|properties| properties.get("walkable")), Some(&MapProperty::Bool(false)));
+        assert!(map.tile_properties(3).is_none());
+    }
+
+    #[test]
+    fn tmj_fixture() {
+        check_fixture(build(parse_tmj(TMJ.as_bytes()).unwrap()));
+    }
+
+    #[test]
+    fn tmx_fixture() {
+        check_fixture(build(parse_tmx(TMX.as_bytes()).unwrap()));
+    }
+
+    #[test]
+    fn infinite_maps_are_reported() {
+        let tmj = r#"{ "width": 0, "height": 0, "tilewidth": 16, "tileheight": 16, "infinite": true, "tilesets": [],
+            "layers": [{ "type": "tilelayer", "chunks": [{ "x": 0, "y": 0, "width": 16, "height": 16, "data": [] }] }] }"#;
+        let tmx = r#"<map width="0" height="0" tilewidth="16" tileheight="16" infinite="1">
+            <layer id="1" name="ground"><data encoding="csv"><chunk x="0" y="0" width="16" height="16">1</chunk></data></layer>
+        </map>"#;
+        for result in [parse_tmj(tmj.as_bytes()), parse_tmx(tmx.as_bytes())] {
+            let Err(err) = result else { panic!("infinite maps should not load") };
+            assert_eq!(err.to_string(), "unsupported Tiled file: infinite maps");
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bevy::prelude::*;
+use derive_more::Display;
+
+use crate::{
+    game_tile::Corner,
+    map_source::MapSource,
+    tile_metadata::TileMetadata,
+    tile_world::TileMetrics,
+};
+
+/// A problem found in the tileset metadata or the training map. Positions are map coordinates as shown in the editor.
+#[derive(Debug, Display, Clone, PartialEq)]
+pub enum TilesetDiagnostic {
+    #[display(fmt = "tile {} is used in the map but has no corner metadata", tile_id)]
+    MissingMetadata { tile_id: i32 },
+    #[display(fmt = "tile {} has terrain for some corners but not for {:?}", tile_id, missing)]
+    IncompleteCorners { tile_id: i32, missing: Vec<Corner> },
+    #[display(
+        fmt = "tiles at ({},{}) and ({},{}) disagree on a shared corner: {} vs {}",
+        "pos.0", "pos.1", "neighbour_pos.0", "neighbour_pos.1", terrain, neighbour_terrain
+    )]
+    CornerMismatch { pos: (i32, i32), neighbour_pos: (i32, i32), terrain: String, neighbour_terrain: String },
+    #[display(fmt = "tile {} is beyond the {}x{} texture atlas", tile_id, "atlas_size.0", "atlas_size.1")]
+    OutsideAtlas { tile_id: i32, atlas_size: (usize, usize) },
+}
+
+/// Diagnostics of the last validation, empty if the tileset is fine
+#[derive(Resource, Default)]
+pub struct TilesetDiagnostics(pub Vec<TilesetDiagnostic>);
+
+/// Validates and logs the problems. With `strict` any problem is an error, the game refuses to start then.
+pub fn check(
+    metadata: &TileMetadata,
+    map: &dyn MapSource,
+    tile_metrics: &TileMetrics,
+    strict: bool,
+) -> Result<Vec<TilesetDiagnostic>, Vec<TilesetDiagnostic>> {
+    let diagnostics = validate(metadata, map, tile_metrics);
+    for diagnostic in &diagnostics {
+        warn!("tileset: {}", diagnostic);
+    }
+    if strict && !diagnostics.is_empty() { Err(diagnostics) } else { Ok(diagnostics) }
+}
+
+/// Checks the tileset metadata against the map it is used with
+pub fn validate(metadata: &TileMetadata, map: &dyn MapSource, tile_metrics: &TileMetrics) -> Vec<TilesetDiagnostic> {
+    let mut diagnostics = vec![];
+    let base_tiles = map.base_tiles();
+
+    let used_tile_ids = base_tiles.iter().map(|tile| tile.tile.tile_id).collect::<BTreeSet<_>>();
+    for &tile_id in &used_tile_ids {
+        if !metadata.tiles.contains_key(&tile_id) {
+            diagnostics.push(TilesetDiagnostic::MissingMetadata { tile_id });
+        }
+    }
+
+    let mut described_tile_ids = metadata.tiles.keys().copied().collect::<Vec<_>>();
+    described_tile_ids.sort();
+    for tile_id in described_tile_ids {
+        let corners = metadata.tiles[&tile_id].corners;
+        let missing = Corner::CLOCKWISE.into_iter()
+            .filter(|corner| corners[*corner as usize].is_none())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() && missing.len() < corners.len() {
+            diagnostics.push(TilesetDiagnostic::IncompleteCorners { tile_id, missing });
+        }
+    }
+
+    // editors count y downwards, so the neighbour below has the larger y
+    let tile_at = base_tiles.iter().map(|tile| ((tile.x, tile.y), tile.tile)).collect::<HashMap<_, _>>();
+    let shared_corners = [
+        ((1, 0), [(Corner::TopRight, Corner::TopLeft), (Corner::BottomRight, Corner::BottomLeft)]),
+        ((0, 1), [(Corner::BottomLeft, Corner::TopLeft), (Corner::BottomRight, Corner::TopRight)]),
+    ];
+    for tile in &base_tiles {
+        for ((dx, dy), corner_pairs) in shared_corners {
+            let neighbour_pos = (tile.x + dx, tile.y + dy);
+            let Some(neighbour) = tile_at.get(&neighbour_pos) else { continue };
+            let mismatch = corner_pairs.into_iter().find_map(|(corner, neighbour_corner)| {
+                let terrain = metadata.corner_type(&tile.tile, corner)?;
+                let neighbour_terrain = metadata.corner_type(neighbour, neighbour_corner)?;
+                (terrain != neighbour_terrain).then_some((terrain, neighbour_terrain))
+            });
+            if let Some((terrain, neighbour_terrain)) = mismatch {
+                diagnostics.push(TilesetDiagnostic::CornerMismatch {
+                    pos: (tile.x, tile.y),
+                    neighbour_pos,
+                    terrain: metadata.terrain(terrain).name.clone(),
+                    neighbour_terrain: metadata.terrain(neighbour_terrain).name.clone(),
+                });
+            }
+        }
+    }
+
+    let atlas_size = (tile_metrics.atlas_columns, tile_metrics.atlas_rows);
+    let object_tile_ids = map.objects().iter().map(|object| object.object.tile_id).collect::<BTreeSet<_>>();
+    for &tile_id in used_tile_ids.union(&object_tile_ids) {
+        if !tile_metrics.atlas_contains(tile_id) {
+            diagnostics.push(TilesetDiagnostic::OutsideAtlas { tile_id, atlas_size });
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game_tile::GameTile,
+        map_source::TestMap,
+        tile_metadata::TilesetFile,
+    };
+
+    const FIELD: i32 = 0;
+    const WATER: i32 = 1;
+    const HALF_CORNERS: i32 = 2;
+    const COAST: i32 = 3; // field on top, water below
+    const UNDESCRIBED: i32 = 4;
+
+    fn metadata() -> TileMetadata {
+        let file: TilesetFile = serde_json::from_str(r#"{
+            "terrains": [
+                { "name": "water", "walkable": false, "cost": 1.0 },
+                { "name": "field", "walkable": true,  "cost": 1.0 }
+            ],
+            "tiles": {
+                "0": { "top_left": "field", "top_right": "field", "bottom_right": "field", "bottom_left": "field" },
+                "1": { "top_left": "water", "top_right": "water", "bottom_right": "water", "bottom_left": "water" },
+                "2": { "top_left": "field", "top_right": "field" },
+                "3": { "top_left": "field", "top_right": "field", "bottom_right": "water", "bottom_left": "water" }
+            }
+        }"#).unwrap();
+        TileMetadata::new(&file, &TestMap::default())
+    }
+
+    /// rows of tile ids, editor coordinates with y pointing down
+    fn map(rows: &[&[i32]]) -> TestMap {
+        let tiles = rows.iter().enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, &tile_id)| (x as i32, y as i32, GameTile { tile_id, orientation: default() })))
+            .collect();
+        TestMap { tiles, objects: vec![] }
+    }
+
+    fn run(map: &TestMap) -> Vec<TilesetDiagnostic> {
+        validate(&metadata(), map, &TileMetrics::new(map))
+    }
+
+    #[test]
+    fn consistent_map() {
+        assert_eq!(run(&map(&[
+            &[FIELD, FIELD],
+            &[COAST, COAST],
+            &[WATER, WATER],
+        ])), [TilesetDiagnostic::IncompleteCorners { tile_id: HALF_CORNERS, missing: vec![Corner::BottomRight, Corner::BottomLeft] }]);
+    }
+
+    #[test]
+    fn missing_metadata() {
+        let diagnostics = run(&map(&[&[FIELD, UNDESCRIBED]]));
+        assert!(diagnostics.contains(&TilesetDiagnostic::MissingMetadata { tile_id: UNDESCRIBED }));
+        assert!(!diagnostics.contains(&TilesetDiagnostic::MissingMetadata { tile_id: FIELD }));
+    }
+
+    #[test]
+    fn incomplete_corners() {
+        // reported for the tileset even if the map doesn't use the tile, tiles without any corners are fine
+        let mut metadata = metadata();
+        metadata.tiles.insert(UNDESCRIBED, default());
+        let map = map(&[&[FIELD]]);
+        assert_eq!(validate(&metadata, &map, &TileMetrics::new(&map)), [
+            TilesetDiagnostic::IncompleteCorners { tile_id: HALF_CORNERS, missing: vec![Corner::BottomRight, Corner::BottomLeft] },
+        ]);
+        metadata.tiles.get_mut(&FIELD).unwrap().corners[Corner::TopRight as usize] = None;
+        assert!(validate(&metadata, &map, &TileMetrics::new(&map))
+            .contains(&TilesetDiagnostic::IncompleteCorners { tile_id: FIELD, missing: vec![Corner::TopRight] }));
+    }
+
+    #[test]
+    fn corner_mismatch() {
+        let diagnostics = run(&map(&[
+            &[FIELD, WATER],
+            &[WATER, WATER],
+        ]));
+        let mismatches = diagnostics.into_iter()
+            .filter(|diagnostic| matches!(diagnostic, TilesetDiagnostic::CornerMismatch { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(mismatches, [
+            TilesetDiagnostic::CornerMismatch { pos: (0, 0), neighbour_pos: (1, 0), terrain: "field".into(), neighbour_terrain: "water".into() },
+            TilesetDiagnostic::CornerMismatch { pos: (0, 0), neighbour_pos: (0, 1), terrain: "field".into(), neighbour_terrain: "water".into() },
+        ]);
+        // the coast tile turns field above into water below
+        assert!(run(&map(&[&[FIELD], &[COAST], &[WATER]])).iter()
+            .all(|diagnostic| !matches!(diagnostic, TilesetDiagnostic::CornerMismatch { .. })));
+    }
+
+    #[test]
+    fn outside_atlas() {
+        let mut map = map(&[&[FIELD, 72]]);
+        map.objects.push((0, 0, 80));
+        map.objects.push((0, 0, 71));
+        let diagnostics = run(&map);
+        let outside = diagnostics.into_iter()
+            .filter(|diagnostic| matches!(diagnostic, TilesetDiagnostic::OutsideAtlas { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(outside, [
+            TilesetDiagnostic::OutsideAtlas { tile_id: 72, atlas_size: (8, 9) },
+            TilesetDiagnostic::OutsideAtlas { tile_id: 80, atlas_size: (8, 9) },
+        ]);
+    }
+
+    #[test]
+    fn strict_check_fails_on_any_problem() {
+        let metadata = metadata();
+        let broken = map(&[&[FIELD, UNDESCRIBED]]);
+        let metrics = TileMetrics::new(&broken);
+        assert!(check(&metadata, &broken, &metrics, false).is_ok_and(|diagnostics| !diagnostics.is_empty()));
+        assert!(check(&metadata, &broken, &metrics, true).is_err());
+
+        let mut fine = metadata;
+        fine.tiles.remove(&HALF_CORNERS);
+        assert_eq!(check(&fine, &map(&[&[FIELD]]), &metrics, true), Ok(vec![]));
+    }
+}
@@ -390,7 +390,8 @@ impl WaveFunctionCollapseGenerator
         work_queue.push_back((x_chosen_tile, y_chosen_tile));
         already_handled.insert((x_chosen_tile, y_chosen_tile));
     
-        let mut recompute_entropy_set = HashSet::<(usize, usize)>::new();
+        // a list, not a set: the entropy noise is drawn in this order, so it has to be the same every run
+        let mut recompute_entropy_tiles = Vec::<(usize, usize)>::new();
     
         while let Some((x_current_tile, y_current_tile)) = work_queue.pop_front() 
         {
@@ -436,7 +437,7 @@ impl WaveFunctionCollapseGenerator
     
                 if has_changed && !already_handled.contains(&(next_x, next_y))
                 {
-                    recompute_entropy_set.insert((next_x, next_y));
+                    recompute_entropy_tiles.push((next_x, next_y));
     
                     work_queue.push_back((next_x, next_y));
                     already_handled.insert((next_x, next_y));
@@ -444,7 +445,7 @@ impl WaveFunctionCollapseGenerator
             }
         }
     
-        for (x, y) in recompute_entropy_set
+        for (x, y) in recompute_entropy_tiles
         {
             *self.entropy_for_tile.get_mut(x, y).unwrap() = self.get_shannon_entropy_for_tile(x, y);
         }